@@ -1,145 +1,1224 @@
 //! Audio recording via cpal.
 
+use crate::error::{Code, ResultExt};
 use anyhow::{Context, Result};
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use std::collections::VecDeque;
 use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
 
 pub const SAMPLE_RATE: u32 = 16000;
 
-/// Records audio until Enter is pressed.
-/// Returns f32 samples at 16kHz mono.
-pub fn record_until_enter(quiet: bool) -> Result<Vec<f32>> {
-    let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .context("No audio input device available")?;
+/// Less audio than this is treated as nothing recorded.
+pub const MIN_AUDIO: Duration = Duration::from_millis(500);
+
+/// How many more times to try opening and starting the input after a
+/// failure, unless `Options::retries` says otherwise. Devices waking from
+/// power-save (Bluetooth mics especially) often fail once, then work.
+pub const DEFAULT_RETRIES: u32 = 2;
+
+/// Pause between attempts to open the input.
+const RETRY_DELAY: Duration = Duration::from_millis(300);
+
+/// True if `samples` at `rate` Hz last less than `MIN_AUDIO`.
+pub fn too_short(samples: &[f32], rate: u32) -> bool {
+    (samples.len() as f64) < MIN_AUDIO.as_secs_f64() * rate as f64
+}
+
+/// Recording settings.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Keep status output to a single self-clearing stderr line
+    pub quiet: bool,
+    /// Wait this long before capture starts, showing a countdown unless quiet
+    pub delay: Option<Duration>,
+    /// Ends the recording when set, as if Enter had been pressed
+    pub stop: Option<Arc<AtomicBool>>,
+    /// Multiply captured samples by this factor, clamping to [-1, 1]
+    pub gain: Option<f32>,
+    /// Play a short tone when capture starts and again when it stops
+    pub beep: bool,
+    /// Devices to record from, mixed together; empty means the default input
+    pub sources: Vec<Source>,
+    /// Stop on this key, read in raw mode, instead of Enter
+    pub stop_key: Option<char>,
+    /// Ignore the keyboard, so only `stop` ends the recording (for a
+    /// recorder running in the background)
+    pub detached: bool,
+    /// Extra attempts at opening the input if it fails (default
+    /// `DEFAULT_RETRIES`)
+    pub retries: Option<u32>,
+    /// Report device fallbacks on stderr
+    pub verbose: bool,
+    /// Play `beep` cues on this output device (a name as for `--device`)
+    /// instead of the default
+    pub output_device: Option<String>,
+}
+
+/// Which input device to record from.
+#[derive(Debug, Default, Clone)]
+pub enum Source {
+    /// The system default input
+    #[default]
+    Default,
+    /// The input with this name, or the only one whose name contains it
+    /// (ignoring case)
+    Named(String),
+    /// A monitor/loopback input that captures what the system is playing
+    Loopback,
+}
+
+/// Name fragments (lowercase) of inputs that capture system output:
+/// PulseAudio/PipeWire monitors, ALSA loopback, Windows "Stereo Mix" and
+/// macOS virtual devices.
+const LOOPBACK_HINTS: &[&str] = &[
+    "monitor",
+    "loopback",
+    "stereo mix",
+    "what u hear",
+    "blackhole",
+    "soundflower",
+];
+
+/// Periodically inspects the most recent audio while recording.
+pub struct Watch<'a> {
+    /// How much recent audio each check sees, at most
+    pub window: Duration,
+    /// How often to check
+    pub every: Duration,
+    /// Checks begin once this much has been recorded
+    pub after: Duration,
+    /// Given the latest `window` of 16kHz audio; returning true stops the
+    /// recording as if Enter had been pressed
+    pub check: &'a mut dyn FnMut(&[f32]) -> bool,
+}
+
+/// Fraction of clamped samples above which `--input-gain` warns.
+const CLIP_WARN_FRACTION: f64 = 0.01;
+
+/// Pitch of the `--beep` cues in Hz: rising for start, falling for stop.
+const BEEP_START_HZ: f32 = 880.0;
+const BEEP_STOP_HZ: f32 = 660.0;
+
+/// Length of each `--beep` cue.
+const BEEP_LENGTH: Duration = Duration::from_millis(120);
+
+/// Extra wait after a cue for the output device to drain its buffer, so the
+/// tail of the tone isn't captured by the microphone.
+const BEEP_DRAIN: Duration = Duration::from_millis(80);
+
+/// How often the recording loop checks for a stop request.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Where captured mono samples go.
+enum Capture {
+    /// Keep everything
+    All(Vec<f32>),
+    /// Keep only the most recent `capacity` samples
+    Last {
+        samples: VecDeque<f32>,
+        capacity: usize,
+    },
+}
+
+impl Extend<f32> for Capture {
+    fn extend<I: IntoIterator<Item = f32>>(&mut self, iter: I) {
+        match self {
+            Capture::All(samples) => samples.extend(iter),
+            Capture::Last { samples, capacity } => {
+                for sample in iter {
+                    if samples.len() == *capacity {
+                        samples.pop_front();
+                    }
+                    samples.push_back(sample);
+                }
+            }
+        }
+    }
+}
+
+impl Capture {
+    /// Removes and returns everything captured so far.
+    fn take(&mut self) -> Vec<f32> {
+        match self {
+            Capture::All(samples) => std::mem::take(samples),
+            Capture::Last { samples, .. } => samples.drain(..).collect(),
+        }
+    }
+}
+
+/// A running stream from one device feeding a `Capture`.
+struct Track {
+    stream: cpal::Stream,
+    /// Device name, for errors
+    name: String,
+    rate: u32,
+    capture: Arc<Mutex<Capture>>,
+    /// First stream error (e.g. the mic being unplugged); samples silently
+    /// stop arriving after one, so it is reported when capture ends
+    error: Arc<Mutex<Option<String>>>,
+}
+
+impl Track {
+    /// Removes the captured audio and returns it resampled to 16kHz.
+    fn take(&self) -> Vec<f32> {
+        let samples = self.capture.lock().unwrap().take();
+        debug!(samples = samples.len(), rate = self.rate, "captured audio");
+        resample(&samples, self.rate, SAMPLE_RATE)
+    }
+
+    /// Returns a copy of the last `length` of captured audio, resampled to
+    /// 16kHz, leaving the capture untouched.
+    fn tail(&self, length: Duration) -> Vec<f32> {
+        let n = (length.as_secs_f64() * self.rate as f64) as usize;
+        let samples: Vec<f32> = match &*self.capture.lock().unwrap() {
+            Capture::All(samples) => samples[samples.len().saturating_sub(n)..].to_vec(),
+            Capture::Last { samples, .. } => samples
+                .iter()
+                .skip(samples.len().saturating_sub(n))
+                .copied()
+                .collect(),
+        };
+        resample(&samples, self.rate, SAMPLE_RATE)
+    }
+
+    /// Warns about a stream error, even in quiet mode: the transcript may be
+    /// missing the end.
+    fn report_error(&self) {
+        if let Some(err) = self.error.lock().unwrap().take() {
+            eprintln!(
+                "\x1b[93mRecording was interrupted ({}); the transcript may be incomplete.\x1b[0m",
+                err
+            );
+        }
+    }
+}
+
+/// Every device being recorded from, mixed down to a single track.
+///
+/// Streams are started one after another and each runs on its own clock,
+/// so they are aligned only by their start: expect a few milliseconds of
+/// offset, and slow drift over long recordings. Fine for speech, not for
+/// anything that needs sample-accurate sync.
+struct Input {
+    tracks: Vec<Track>,
+}
+
+impl Input {
+    fn play(&self) -> Result<()> {
+        for track in &self.tracks {
+            track
+                .stream
+                .play()
+                .with_context(|| format!("Failed to start audio input '{}'", track.name))
+                .code(Code::NoDevice)?;
+        }
+        Ok(())
+    }
+
+    fn pause(&self) {
+        for track in &self.tracks {
+            track.stream.pause().ok();
+        }
+    }
+
+    fn take(&self) -> Vec<f32> {
+        mix(self.tracks.iter().map(Track::take).collect())
+    }
+
+    fn tail(&self, length: Duration) -> Vec<f32> {
+        mix(self.tracks.iter().map(|t| t.tail(length)).collect())
+    }
+
+    fn report_error(&self) {
+        for track in &self.tracks {
+            track.report_error();
+        }
+    }
+}
+
+/// Averages equal-rate tracks sample by sample. Where one track runs
+/// short, the rest are averaged without it rather than diluted by silence.
+fn mix(mut tracks: Vec<Vec<f32>>) -> Vec<f32> {
+    if tracks.len() == 1 {
+        return tracks.remove(0);
+    }
+    let len = tracks.iter().map(Vec::len).max().unwrap_or(0);
+    (0..len)
+        .map(|i| {
+            let (sum, count) = tracks
+                .iter()
+                .filter_map(|t| t.get(i))
+                .fold((0.0, 0), |(sum, count), s| (sum + s, count + 1));
+            sum / count as f32
+        })
+        .collect()
+}
+
+/// Finds the device for `source` along with the config to capture with.
+fn select_device(
+    host: &cpal::Host,
+    source: &Source,
+    verbose: bool,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    let device = match source {
+        Source::Default => host
+            .default_input_device()
+            .context("No audio input device available")?,
+        Source::Named(name) => find_device(
+            host.input_devices()
+                .context("Failed to list input devices")?,
+            name,
+            "input",
+        )?,
+        Source::Loopback => {
+            let monitor = host
+                .input_devices()
+                .context("Failed to list input devices")?
+                .find(|d| {
+                    d.name().is_ok_and(|n| {
+                        let n = n.to_lowercase();
+                        LOOPBACK_HINTS.iter().any(|hint| n.contains(hint))
+                    })
+                });
+            match monitor {
+                Some(device) => device,
+                // WASAPI records an output device's mix when asked to
+                // capture from it
+                None if cfg!(windows) => {
+                    let device = host
+                        .default_output_device()
+                        .context("No audio output device to capture from")?;
+                    let config = device
+                        .default_output_config()
+                        .context("Failed to get default output config")?;
+                    return Ok((device, config));
+                }
+                None => anyhow::bail!(
+                    "No loopback or monitor input found; this needs OS support \
+                     (e.g. a PulseAudio/PipeWire monitor source, or BlackHole on macOS)"
+                ),
+            }
+        }
+    };
 
     // Get the default config - most reliable
-    let default_config = device
-        .default_input_config()
-        .context("Failed to get default input config")?;
+    let config = match device.default_input_config() {
+        Ok(config) => config,
+        Err(e) => {
+            let Some(config) = fallback_input_config(&device) else {
+                return Err(e).context("Failed to get default input config");
+            };
+            let name = device.name().unwrap_or_default();
+            debug!(device = name, error = %e, config = ?config, "default input config failed");
+            if verbose {
+                eprintln!(
+                    "\x1b[90m(No default config for '{}' ({}); using {} Hz, {} channel(s), {:?})\x1b[0m",
+                    name,
+                    e,
+                    config.sample_rate().0,
+                    config.channels(),
+                    config.sample_format()
+                );
+            }
+            config
+        }
+    };
+    Ok((device, config))
+}
+
+/// Picks a config from the ones `device` says it supports, for when it has
+/// no usable default: a sample format and channel count the capture code
+/// handles, the rate closest to `SAMPLE_RATE`, then mono, then f32.
+fn fallback_input_config(device: &cpal::Device) -> Option<cpal::SupportedStreamConfig> {
+    device
+        .supported_input_configs()
+        .ok()?
+        .filter(|range| {
+            matches!(range.channels(), 1 | 2)
+                && matches!(
+                    range.sample_format(),
+                    cpal::SampleFormat::F32 | cpal::SampleFormat::I16 | cpal::SampleFormat::I32
+                )
+        })
+        .map(|range| {
+            let rate = SAMPLE_RATE.clamp(range.min_sample_rate().0, range.max_sample_rate().0);
+            range.with_sample_rate(cpal::SampleRate(rate))
+        })
+        .min_by_key(|config| {
+            (
+                config.sample_rate().0.abs_diff(SAMPLE_RATE),
+                config.channels(),
+                config.sample_format() != cpal::SampleFormat::F32,
+            )
+        })
+}
+
+/// Picks the device called `name` from `devices`, or else the only one whose
+/// name contains it (ignoring case). `kind` ("input" or "output") names the
+/// devices in errors.
+fn find_device(
+    devices: impl Iterator<Item = cpal::Device>,
+    name: &str,
+    kind: &str,
+) -> Result<cpal::Device> {
+    let devices: Vec<(cpal::Device, String)> = devices
+        .filter_map(|d| d.name().ok().map(|n| (d, n)))
+        .collect();
+    let wanted = name.to_lowercase();
+    let mut matches: Vec<_> = match devices.iter().position(|(_, n)| n == name) {
+        Some(i) => vec![&devices[i]],
+        None => devices
+            .iter()
+            .filter(|(_, n)| n.to_lowercase().contains(&wanted))
+            .collect(),
+    };
+    match matches.len() {
+        1 => Ok(matches.remove(0).0.clone()),
+        0 => anyhow::bail!(
+            "No {} device matches '{}'. Available: {}",
+            kind,
+            name,
+            device_list(devices.iter().map(|(_, n)| n))
+        ),
+        _ => anyhow::bail!(
+            "'{}' matches several {} devices: {}",
+            name,
+            kind,
+            device_list(matches.iter().map(|(_, n)| n))
+        ),
+    }
+}
+
+/// Joins device names for error messages.
+fn device_list<'a>(names: impl Iterator<Item = &'a String>) -> String {
+    let names: Vec<&str> = names.map(String::as_str).collect();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(", ")
+    }
+}
+
+/// Opens every device in `sources` (the default input if empty). See
+/// `open_track`.
+fn open_input(
+    ring: Option<Duration>,
+    stop: &Arc<AtomicBool>,
+    sources: &[Source],
+    verbose: bool,
+) -> Result<Input> {
+    let tracks = match sources {
+        [] => vec![open_track(ring, stop, &Source::Default, verbose)?],
+        _ => sources
+            .iter()
+            .map(|source| open_track(ring, stop, source, verbose))
+            .collect::<Result<_>>()?,
+    };
+    Ok(Input { tracks })
+}
+
+/// Opens and starts the input for `sources`, trying again up to `retries`
+/// times if either step fails. When every attempt fails, the error is the
+/// last attempt's, noting how many attempts were made.
+fn start_input(
+    ring: Option<Duration>,
+    stop: &Arc<AtomicBool>,
+    sources: &[Source],
+    retries: u32,
+    verbose: bool,
+) -> Result<Input> {
+    let mut attempt = 0;
+    loop {
+        let result = open_input(ring, stop, sources, verbose).and_then(|input| {
+            input.play()?;
+            Ok(input)
+        });
+        match result {
+            Ok(input) => return Ok(input),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                debug!(error = %format!("{:#}", e), attempt, "audio input failed, retrying");
+                thread::sleep(RETRY_DELAY);
+            }
+            Err(e) => {
+                let attempts = attempt + 1;
+                return Err(e)
+                    .context(format!(
+                        "Audio input failed after {} attempt{}",
+                        attempts,
+                        if attempts == 1 { "" } else { "s" }
+                    ))
+                    .code(Code::NoDevice);
+            }
+        }
+    }
+}
+
+/// Opens the input device for `source` at its native rate and channels,
+/// mixing down to mono. With `ring`, only the most recent audio of that
+/// length is kept. Samples are dropped while `stop` is set.
+fn open_track(
+    ring: Option<Duration>,
+    stop: &Arc<AtomicBool>,
+    source: &Source,
+    verbose: bool,
+) -> Result<Track> {
+    let host = cpal::default_host();
+    let (device, default_config) = select_device(&host, source, verbose).code(Code::NoDevice)?;
+    let name = device
+        .name()
+        .unwrap_or_else(|_| "unknown device".to_string());
+    let open_failed = || format!("Failed to open audio input '{}'", name);
 
     let device_sample_rate = default_config.sample_rate().0;
     let channels = default_config.channels() as usize;
     let sample_format = default_config.sample_format();
+    debug!(
+        device = name,
+        rate = device_sample_rate,
+        channels,
+        format = ?sample_format,
+        "opened input device"
+    );
 
     let config: cpal::StreamConfig = default_config.into();
 
-    let samples: Arc<Mutex<Vec<f32>>> = Arc::new(Mutex::new(Vec::new()));
-    let stop_flag = Arc::new(AtomicBool::new(false));
+    let capture = match ring {
+        Some(length) => {
+            let capacity = ((length.as_secs_f64() * device_sample_rate as f64) as usize).max(1);
+            Capture::Last {
+                samples: VecDeque::with_capacity(capacity),
+                capacity,
+            }
+        }
+        None => Capture::All(Vec::new()),
+    };
+    let samples = Arc::new(Mutex::new(capture));
 
     let samples_clone = Arc::clone(&samples);
-    let stop_clone = Arc::clone(&stop_flag);
+    let stop_clone = Arc::clone(stop);
 
-    let err_fn = |err| eprintln!("Audio stream error: {}", err);
+    let stream_error: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let err_fn = || {
+        let stream_error = Arc::clone(&stream_error);
+        move |err: cpal::StreamError| {
+            stream_error
+                .lock()
+                .unwrap()
+                .get_or_insert_with(|| err.to_string());
+        }
+    };
 
     // Capture at device's native rate and channels
     let stream = match sample_format {
-        cpal::SampleFormat::F32 => {
-            device.build_input_stream(
+        cpal::SampleFormat::F32 => device
+            .build_input_stream(
                 &config,
                 move |data: &[f32], _: &_| {
                     if !stop_clone.load(Ordering::Relaxed) {
                         let mut samples = samples_clone.lock().unwrap();
-                        // Convert to mono if stereo
-                        if channels == 2 {
-                            samples.extend(data.chunks(2).map(|c| (c[0] + c[1]) / 2.0));
-                        } else {
-                            samples.extend_from_slice(data);
-                        }
+                        samples.extend(mono(data, channels, |s| s));
                     }
                 },
-                err_fn,
+                err_fn(),
                 None,
-            )?
-        }
+            )
+            .with_context(open_failed)
+            .code(Code::NoDevice)?,
         cpal::SampleFormat::I16 => {
             let samples_clone = Arc::clone(&samples);
-            let stop_clone = Arc::clone(&stop_flag);
-            device.build_input_stream(
-                &config,
-                move |data: &[i16], _: &_| {
-                    if !stop_clone.load(Ordering::Relaxed) {
-                        let mut samples = samples_clone.lock().unwrap();
-                        if channels == 2 {
-                            samples.extend(data.chunks(2).map(|c| {
-                                (c[0] as f32 / 32768.0 + c[1] as f32 / 32768.0) / 2.0
-                            }));
-                        } else {
-                            samples.extend(data.iter().map(|&s| s as f32 / 32768.0));
+            let stop_clone = Arc::clone(stop);
+            device
+                .build_input_stream(
+                    &config,
+                    move |data: &[i16], _: &_| {
+                        if !stop_clone.load(Ordering::Relaxed) {
+                            let mut samples = samples_clone.lock().unwrap();
+                            samples.extend(mono(data, channels, |s| s as f32 / 32768.0));
                         }
-                    }
-                },
-                err_fn,
-                None,
-            )?
+                    },
+                    err_fn(),
+                    None,
+                )
+                .with_context(open_failed)
+                .code(Code::NoDevice)?
         }
         cpal::SampleFormat::I32 => {
             let samples_clone = Arc::clone(&samples);
-            let stop_clone = Arc::clone(&stop_flag);
-            device.build_input_stream(
-                &config,
-                move |data: &[i32], _: &_| {
-                    if !stop_clone.load(Ordering::Relaxed) {
-                        let mut samples = samples_clone.lock().unwrap();
-                        if channels == 2 {
-                            samples.extend(data.chunks(2).map(|c| {
-                                (c[0] as f32 / 2147483648.0 + c[1] as f32 / 2147483648.0) / 2.0
-                            }));
-                        } else {
-                            samples.extend(data.iter().map(|&s| s as f32 / 2147483648.0));
+            let stop_clone = Arc::clone(stop);
+            device
+                .build_input_stream(
+                    &config,
+                    move |data: &[i32], _: &_| {
+                        if !stop_clone.load(Ordering::Relaxed) {
+                            let mut samples = samples_clone.lock().unwrap();
+                            samples.extend(mono(data, channels, |s| s as f32 / 2147483648.0));
                         }
-                    }
-                },
-                err_fn,
-                None,
-            )?
+                    },
+                    err_fn(),
+                    None,
+                )
+                .with_context(open_failed)
+                .code(Code::NoDevice)?
         }
-        _ => anyhow::bail!("Unsupported sample format: {:?}", sample_format),
+        _ => anyhow::bail!(
+            "Unsupported sample format on audio input '{}': {:?}",
+            name,
+            sample_format
+        ),
     };
 
-    stream.play().context("Failed to start audio stream")?;
+    Ok(Track {
+        stream,
+        name,
+        rate: device_sample_rate,
+        capture: samples,
+        error: stream_error,
+    })
+}
 
-    // Show status message
-    if quiet {
-        eprint!("\x1b[90mListening... (press Enter)\x1b[0m");
-    } else {
-        eprintln!("\x1b[93m[Recording...]\x1b[0m Press ENTER when done.");
+/// Averages interleaved frames of `channels` samples, scaled to f32 by
+/// `scale`, down to mono. Any channel count works, so a 5.1 output captured
+/// as loopback mixes all six channels.
+fn mono<T: Copy>(
+    data: &[T],
+    channels: usize,
+    scale: impl Fn(T) -> f32,
+) -> impl Iterator<Item = f32> {
+    data.chunks(channels.max(1))
+        .map(move |frame| frame.iter().map(|&s| scale(s)).sum::<f32>() / frame.len() as f32)
+}
+
+/// Lists output device names, plus the name of the default output device.
+pub fn output_devices() -> Result<(Vec<String>, Option<String>)> {
+    let host = cpal::default_host();
+    let names = host
+        .output_devices()
+        .context("Failed to list output devices")?
+        .filter_map(|d| d.name().ok())
+        .collect();
+    let default = host.default_output_device().and_then(|d| d.name().ok());
+    Ok((names, default))
+}
+
+/// Lists input device names, plus the name of the default input device.
+pub fn input_devices() -> Result<(Vec<String>, Option<String>)> {
+    let host = cpal::default_host();
+    let names = host
+        .input_devices()
+        .context("Failed to list input devices")?
+        .filter_map(|d| d.name().ok())
+        .collect();
+    let default = host.default_input_device().and_then(|d| d.name().ok());
+    Ok((names, default))
+}
+
+/// Records audio until Enter is pressed.
+/// Returns f32 samples at 16kHz mono.
+///
+/// With `watch`, its check runs on the latest audio every `watch.every` (once
+/// `watch.after` has been captured) and can end the recording early.
+pub fn record_until_enter(opts: &Options, mut watch: Option<Watch>) -> Result<Vec<f32>> {
+    let quiet = opts.quiet;
+    // Silences this recording's capture once it ends. `opts.stop` is only
+    // read, so one flag can serve back-to-back recordings
+    let done = Arc::new(AtomicBool::new(false));
+    let stop_requested = || {
+        opts.stop
+            .as_ref()
+            .is_some_and(|s| s.load(Ordering::Relaxed))
+    };
+
+    let input = begin(None, &done, opts)?;
+
+    // A terminal that can't do raw mode still works with Enter
+    let raw = opts.stop_key.and_then(|key| match RawKeys::new(key) {
+        Ok(raw) => Some(raw),
+        Err(e) => {
+            eprintln!(
+                "\x1b[93mWarning:\x1b[0m Can't read single keys here ({:#}); press Enter to stop",
+                e
+            );
+            None
+        }
+    });
+    let key_name = match &raw {
+        Some(raw) => raw.name(),
+        None => "Enter".to_string(),
+    };
+
+    // Show status message (unless nobody is watching). Raw mode is already
+    // on, so lines need an explicit carriage return
+    if !opts.detached {
+        if quiet {
+            eprint!("\x1b[90mListening... (press {})\x1b[0m", key_name);
+        } else {
+            eprint!(
+                "\x1b[93m[Recording...]\x1b[0m Press {} when done.\r\n",
+                key_name.to_uppercase()
+            );
+        }
+        io::stderr().flush().ok();
     }
-    io::stderr().flush().ok();
 
-    // Wait for Enter
-    let stdin = io::stdin();
-    let _ = stdin.lock().lines().next();
+    // Wait for the key (or EOF, or an external stop), ignoring earlier presses
+    let mut keys = match raw {
+        Some(raw) => Keys::Raw(raw),
+        None if opts.detached => Keys::Ignored,
+        None => {
+            let enter = enter_presses();
+            while enter.try_recv().is_ok() {}
+            Keys::Enter(enter)
+        }
+    };
+    let started = Instant::now();
+    let mut last_check = started;
+    while !stop_requested() {
+        if keys.pressed(POLL_INTERVAL) {
+            break;
+        }
+        if let Some(watch) = &mut watch
+            && started.elapsed() >= watch.after
+            && last_check.elapsed() >= watch.every
+        {
+            last_check = Instant::now();
+            if (watch.check)(&input.tail(watch.window)) {
+                break;
+            }
+        }
+    }
 
     // Clear the status line in quiet mode
-    if quiet {
+    if quiet && !opts.detached {
         eprint!("\r\x1b[K");
         io::stderr().flush().ok();
     }
 
-    stop_flag.store(true, Ordering::Relaxed);
-    drop(stream);
+    drop(keys);
+    done.store(true, Ordering::Relaxed);
+    input.pause();
+    input.report_error();
+
+    if opts.beep {
+        cue(BEEP_STOP_HZ, opts.output_device.as_deref());
+    }
+
+    let mut samples = input.take();
+    amplify(&mut samples, opts.gain);
+    Ok(samples)
+}
+
+/// Opens the input for `opts.sources`, waits out `opts.delay` and plays
+/// the start cue, then starts capture (with `ring` as for `open_input`).
+fn begin(ring: Option<Duration>, stop: &Arc<AtomicBool>, opts: &Options) -> Result<Input> {
+    let retries = opts.retries.unwrap_or(DEFAULT_RETRIES);
+
+    // Opened before the countdown so a missing device fails fast, then
+    // started after it; a failed start gets a fresh open and the retries
+    let input = open_input(ring, stop, &opts.sources, opts.verbose);
+
+    if let Some(delay) = opts.delay {
+        countdown(delay, opts.quiet);
+    }
+
+    // The cue finishes before capture starts, so it never ends up in the audio
+    if opts.beep {
+        cue(BEEP_START_HZ, opts.output_device.as_deref());
+    }
+
+    match input.and_then(|input| input.play().map(|()| input)) {
+        Ok(input) => Ok(input),
+        Err(e) if retries > 0 => {
+            debug!(error = %format!("{:#}", e), attempt = 1, "audio input failed, retrying");
+            thread::sleep(RETRY_DELAY);
+            start_input(ring, stop, &opts.sources, retries - 1, opts.verbose)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Applies `--input-gain`, warning if much of the audio clipped.
+fn amplify(samples: &mut [f32], gain: Option<f32>) {
+    let Some(gain) = gain else {
+        return;
+    };
+    let clipped = apply_gain(samples, gain);
+    if !samples.is_empty() && clipped as f64 / samples.len() as f64 > CLIP_WARN_FRACTION {
+        eprintln!(
+            "\x1b[93mWarning:\x1b[0m {:.1}% of samples clipped at gain {}; try a lower --input-gain",
+            clipped as f64 * 100.0 / samples.len() as f64,
+            gain
+        );
+    }
+}
+
+/// Multiplies samples by `gain`, clamping to [-1, 1] so loud input doesn't
+/// wrap. Returns how many samples had to be clamped.
+fn apply_gain(samples: &mut [f32], gain: f32) -> usize {
+    let mut clipped = 0;
+    for sample in samples {
+        let amplified = *sample * gain;
+        if amplified.abs() > 1.0 {
+            clipped += 1;
+        }
+        *sample = amplified.clamp(-1.0, 1.0);
+    }
+    clipped
+}
+
+/// Records continuously, keeping only the last `length` of audio in memory.
+///
+/// Each Enter press hands the buffered audio (16kHz mono) to `on_trigger`
+/// and starts the buffer afresh. Returns when stdin closes. `opts` applies
+/// as for `record_until_enter`, except that the keyboard is always read
+/// line by line (`stop_key`, `stop` and `detached` are ignored); with
+/// `beep`, the stop cue plays at each Enter, with capture paused.
+pub fn listen(
+    length: Duration,
+    opts: &Options,
+    mut on_trigger: impl FnMut(Vec<f32>) -> Result<()>,
+) -> Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let input = begin(Some(length), &stop, opts)?;
+
+    if !opts.quiet {
+        eprintln!(
+            "\x1b[93m[Listening...]\x1b[0m Press ENTER to transcribe the last {:.0}s (Ctrl-D to quit).",
+            length.as_secs_f64()
+        );
+    }
+
+    let enter = enter_presses();
+    while enter.try_recv().is_ok() {}
+    while enter.recv().is_ok() {
+        input.report_error();
+        let mut samples = input.take();
+        if opts.beep {
+            input.pause();
+            cue(BEEP_STOP_HZ, opts.output_device.as_deref());
+            input.play()?;
+        }
+        amplify(&mut samples, opts.gain);
+        on_trigger(samples)?;
+    }
+
+    Ok(())
+}
+
+/// Where `record_until_enter` waits for its stop key.
+enum Keys {
+    Enter(MutexGuard<'static, Receiver<()>>),
+    Raw(RawKeys),
+    /// Nothing to wait for; the stop flag ends the recording
+    Ignored,
+}
+
+impl Keys {
+    /// Waits up to `timeout` for the stop key; also true once input closes.
+    fn pressed(&mut self, timeout: Duration) -> bool {
+        match self {
+            Keys::Enter(enter) => match enter.recv_timeout(timeout) {
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                Err(RecvTimeoutError::Timeout) => false,
+            },
+            Keys::Raw(raw) => raw.pressed(timeout),
+            Keys::Ignored => {
+                thread::sleep(timeout);
+                false
+            }
+        }
+    }
+}
+
+/// Single key presses read with the terminal in raw mode, which is restored
+/// when this is dropped.
+struct RawKeys {
+    key: char,
+}
+
+impl RawKeys {
+    fn new(key: char) -> Result<RawKeys> {
+        crossterm::terminal::enable_raw_mode().context("raw mode unavailable")?;
+        let raw = RawKeys { key };
+        // Drop keys typed before recording started
+        while crossterm::event::poll(Duration::ZERO).unwrap_or(false) {
+            let _ = crossterm::event::read();
+        }
+        Ok(raw)
+    }
+
+    fn name(&self) -> String {
+        match self.key {
+            ' ' => "Space".to_string(),
+            key => format!("'{}'", key),
+        }
+    }
+
+    fn pressed(&mut self, timeout: Duration) -> bool {
+        use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+
+        if !crossterm::event::poll(timeout).unwrap_or(false) {
+            return false;
+        }
+        let Ok(Event::Key(event)) = crossterm::event::read() else {
+            return false;
+        };
+        if event.kind != KeyEventKind::Press {
+            return false;
+        }
+        match event.code {
+            // Raw mode swallows Ctrl-C, so deliver it as the signal it
+            // would have been, after giving the terminal back
+            KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                crossterm::terminal::disable_raw_mode().ok();
+                #[cfg(unix)]
+                unsafe {
+                    libc::raise(libc::SIGINT);
+                }
+                true
+            }
+            KeyCode::Char(c) => c == self.key,
+            _ => false,
+        }
+    }
+}
+
+impl Drop for RawKeys {
+    fn drop(&mut self) {
+        crossterm::terminal::disable_raw_mode().ok();
+    }
+}
+
+/// Returns a receiver that gets a message each time Enter is pressed.
+///
+/// A single thread reads stdin for the life of the process, so repeated
+/// recordings don't race each other for input.
+fn enter_presses() -> MutexGuard<'static, Receiver<()>> {
+    static PRESSES: OnceLock<Mutex<Receiver<()>>> = OnceLock::new();
+
+    PRESSES
+        .get_or_init(|| {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                for line in io::stdin().lock().lines() {
+                    if line.is_err() || tx.send(()).is_err() {
+                        break;
+                    }
+                }
+            });
+            Mutex::new(rx)
+        })
+        .lock()
+        .unwrap()
+}
+
+/// Waits for `delay`, counting down whole seconds on stderr unless quiet.
+fn countdown(delay: Duration, quiet: bool) {
+    if quiet {
+        thread::sleep(delay);
+        return;
+    }
+
+    // Sleep off the fractional part first so the count lands on whole seconds
+    let whole = delay.as_secs();
+    thread::sleep(delay - Duration::from_secs(whole));
+    for remaining in (1..=whole).rev() {
+        eprint!("\r\x1b[93mRecording in {}...\x1b[0m\x1b[K", remaining);
+        io::stderr().flush().ok();
+        thread::sleep(Duration::from_secs(1));
+    }
+    eprint!("\r\x1b[K");
+    io::stderr().flush().ok();
+}
+
+/// True if `path` opens as a WAV file `read_wav` can read.
+pub fn is_wav(path: &Path) -> bool {
+    hound::WavReader::open(path).is_ok()
+}
+
+/// Reads a WAV file as 16kHz mono f32 samples.
+///
+/// Multi-channel audio is averaged down to mono. Other sample rates (from
+/// the WAV header) are resampled, or rejected if `allow_resample` is false.
+pub fn read_wav(path: &Path, allow_resample: bool) -> Result<Vec<f32>> {
+    let mut reader = hound::WavReader::open(path)
+        .with_context(|| format!("Failed to open WAV file {}", path.display()))?;
+    let spec = reader.spec();
+    debug!(path = %path.display(), spec = ?spec, "reading WAV file");
+
+    // Integer PCM of any depth hound reads (8, 16, 24 or 32 bits) is scaled
+    // by its own full range; hound already centers 8-bit's unsigned samples
+    let interleaved: Vec<f32> = match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, bits @ 1..=32) => {
+            let scale = (1u64 << (bits - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+        (hound::SampleFormat::Float, 32) => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        (format, bits) => anyhow::bail!(
+            "Unsupported WAV format: {}-bit {:?} (expected 8/16/24/32-bit PCM or 32-bit float)",
+            bits,
+            format
+        ),
+    };
+
+    let channels = spec.channels.max(1) as usize;
+    let mono: Vec<f32> = if channels == 1 {
+        interleaved
+    } else {
+        interleaved
+            .chunks(channels)
+            .map(|c| c.iter().sum::<f32>() / c.len() as f32)
+            .collect()
+    };
+
+    to_sample_rate(mono, spec.sample_rate, allow_resample)
+}
+
+/// Reads raw mono 32-bit float little-endian samples recorded at `rate`,
+/// converting to 16kHz as `read_wav` does.
+pub fn read_raw(mut reader: impl io::Read, rate: u32, allow_resample: bool) -> Result<Vec<f32>> {
+    let mut bytes = Vec::new();
+    reader
+        .read_to_end(&mut bytes)
+        .context("Failed to read raw audio")?;
+    if bytes.len() % 4 != 0 {
+        anyhow::bail!(
+            "Raw audio is {} bytes, not a whole number of 32-bit float samples",
+            bytes.len()
+        );
+    }
+
+    let samples = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    to_sample_rate(samples, rate, allow_resample)
+}
+
+/// Resamples mono audio at `rate` to 16kHz, or errors if that would be
+/// needed and `allow_resample` is false.
+fn to_sample_rate(samples: Vec<f32>, rate: u32, allow_resample: bool) -> Result<Vec<f32>> {
+    if rate == SAMPLE_RATE {
+        return Ok(samples);
+    }
+    if !allow_resample {
+        anyhow::bail!(
+            "Input is {}Hz but --no-resample requires {}Hz audio",
+            rate,
+            SAMPLE_RATE
+        );
+    }
+    Ok(resample(&samples, rate, SAMPLE_RATE))
+}
+
+/// Sample encoding for saved WAV files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WavDepth {
+    /// 16-bit PCM: half the size, plenty for speech
+    #[default]
+    #[value(name = "16")]
+    Pcm16,
+    /// 32-bit float: exactly the samples Whisper was given
+    #[value(name = "f32")]
+    Float32,
+}
+
+/// Writes 16kHz mono samples to a WAV file at the given depth.
+pub fn write_wav(path: &Path, samples: &[f32], depth: WavDepth) -> Result<()> {
+    let (bits_per_sample, sample_format) = match depth {
+        WavDepth::Pcm16 => (16, hound::SampleFormat::Int),
+        WavDepth::Float32 => (32, hound::SampleFormat::Float),
+    };
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample,
+        sample_format,
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec)
+        .with_context(|| format!("Failed to create WAV file {}", path.display()))?;
+    for &sample in samples {
+        match depth {
+            // Clamp first so out-of-range samples saturate instead of wrapping
+            WavDepth::Pcm16 => {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?
+            }
+            WavDepth::Float32 => writer.write_sample(sample)?,
+        }
+    }
+    writer.finalize().context("Failed to finish WAV file")?;
+
+    Ok(())
+}
+
+/// Audio playing on an output device; stops when dropped.
+pub struct Playback {
+    _stream: cpal::Stream,
+    done: Arc<AtomicBool>,
+}
+
+impl Playback {
+    /// Returns true once every sample has been played.
+    pub fn is_done(&self) -> bool {
+        self.done.load(Ordering::Relaxed)
+    }
+}
+
+/// Starts playing 16kHz mono samples on the output device named `device`
+/// (see `output_device`), or the default output without one or if it
+/// can't be found.
+pub fn play(samples: &[f32], device: Option<&str>) -> Result<Playback> {
+    let host = cpal::default_host();
+    let device = output_device(&host, device)?;
+    let default_config = device
+        .default_output_config()
+        .context("Failed to get default output config")?;
 
-    let samples = Arc::try_unwrap(samples)
-        .map_err(|_| anyhow::anyhow!("Failed to unwrap samples"))?
-        .into_inner()
-        .unwrap();
+    let device_sample_rate = default_config.sample_rate().0;
+    let channels = default_config.channels() as usize;
+    let sample_format = default_config.sample_format();
+    let config: cpal::StreamConfig = default_config.into();
 
-    // Resample to 16kHz if needed
-    if device_sample_rate != SAMPLE_RATE {
-        Ok(resample(&samples, device_sample_rate, SAMPLE_RATE))
+    let samples = if device_sample_rate != SAMPLE_RATE {
+        resample(samples, SAMPLE_RATE, device_sample_rate)
     } else {
-        Ok(samples)
+        samples.to_vec()
+    };
+
+    let done = Arc::new(AtomicBool::new(false));
+    let err_fn = |err| eprintln!("Audio stream error: {}", err);
+
+    // Each callback fills whole frames, repeating the mono sample per channel
+    let mut next = {
+        let done = Arc::clone(&done);
+        let mut pos = 0;
+        move || {
+            let sample = samples.get(pos).copied();
+            pos += 1;
+            if sample.is_none() {
+                done.store(true, Ordering::Relaxed);
+            }
+            sample.unwrap_or(0.0)
+        }
+    };
+
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &_| {
+                for frame in data.chunks_mut(channels) {
+                    frame.fill(next());
+                }
+            },
+            err_fn,
+            None,
+        )?,
+        cpal::SampleFormat::I16 => device.build_output_stream(
+            &config,
+            move |data: &mut [i16], _: &_| {
+                for frame in data.chunks_mut(channels) {
+                    frame.fill((next() * i16::MAX as f32) as i16);
+                }
+            },
+            err_fn,
+            None,
+        )?,
+        _ => anyhow::bail!("Unsupported output sample format: {:?}", sample_format),
+    };
+
+    stream.play().context("Failed to start playback")?;
+
+    Ok(Playback {
+        _stream: stream,
+        done,
+    })
+}
+
+/// Warns once that the `--output-device` name matched nothing usable.
+static OUTPUT_FALLBACK: std::sync::Once = std::sync::Once::new();
+
+/// The output device `name` picks as `find_device` does, or the default
+/// output without a name. A name that doesn't pick exactly one device warns
+/// (once per run) and falls back to the default, since sound on the wrong
+/// device beats none.
+fn output_device(host: &cpal::Host, name: Option<&str>) -> Result<cpal::Device> {
+    if let Some(name) = name {
+        let found = host
+            .output_devices()
+            .context("Failed to list output devices")
+            .and_then(|devices| find_device(devices, name, "output"));
+        match found {
+            Ok(device) => return Ok(device),
+            Err(e) => OUTPUT_FALLBACK.call_once(|| {
+                eprintln!("\x1b[93mWarning:\x1b[0m {:#}; using the default output", e)
+            }),
+        }
+    }
+    host.default_output_device()
+        .context("No audio output device available")
+}
+
+/// Plays a short sine tone on `device` (see `play`) and waits for it to
+/// finish. A missing or broken output device only costs the cue, never the
+/// recording.
+fn cue(frequency: f32, device: Option<&str>) {
+    let len = (BEEP_LENGTH.as_secs_f32() * SAMPLE_RATE as f32) as usize;
+    // Ramp in and out over 5ms so the tone doesn't click
+    let ramp = SAMPLE_RATE as f32 * 0.005;
+    let tone: Vec<f32> = (0..len)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = (i as f32 / ramp).min((len - i) as f32 / ramp).min(1.0);
+            0.3 * envelope * (2.0 * std::f32::consts::PI * frequency * t).sin()
+        })
+        .collect();
+
+    match play(&tone, device) {
+        Ok(playback) => {
+            let deadline = std::time::Instant::now() + BEEP_LENGTH * 4;
+            while !playback.is_done() && std::time::Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            thread::sleep(BEEP_DRAIN);
+        }
+        Err(e) => eprintln!("\x1b[93mWarning:\x1b[0m Couldn't play --beep cue: {:#}", e),
     }
 }
 
 /// Simple linear resampling
+///
+/// Empty input (or a zero rate) yields empty output; any non-empty input
+/// yields at least one sample, so very short clips aren't silently dropped.
 fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
+    if samples.is_empty() || from_rate == 0 || to_rate == 0 {
+        return Vec::new();
+    }
+    if from_rate == to_rate {
+        return samples.to_vec();
+    }
+
     let ratio = from_rate as f64 / to_rate as f64;
-    let new_len = (samples.len() as f64 / ratio) as usize;
+    let new_len = ((samples.len() as f64 / ratio).round() as usize).max(1);
+    debug!(
+        from_rate,
+        to_rate,
+        ratio,
+        samples = samples.len(),
+        new_len,
+        "resampling"
+    );
     let mut output = Vec::with_capacity(new_len);
 
     for i in 0..new_len {
@@ -149,13 +1228,215 @@ fn resample(samples: &[f32], from_rate: u32, to_rate: u32) -> Vec<f32> {
 
         let sample = if idx + 1 < samples.len() {
             samples[idx] * (1.0 - frac as f32) + samples[idx + 1] * frac as f32
-        } else if idx < samples.len() {
-            samples[idx]
         } else {
-            0.0
+            // Rounding the length up can step just past the end; hold the last sample
+            samples[idx.min(samples.len() - 1)]
         };
         output.push(sample);
     }
 
     output
 }
+
+/// Frame length used for silence detection (30ms at 16kHz).
+const SILENCE_FRAME_LEN: usize = 480;
+
+/// RMS level below which a frame counts as silence.
+const SILENCE_RMS: f32 = 0.01;
+
+/// Finds interior silences lasting at least `min_gap` seconds in 16kHz
+/// samples, as sample ranges. Leading and trailing silence is ignored.
+pub fn find_silences(samples: &[f32], min_gap: f32) -> Vec<Range<usize>> {
+    let gap_frames = ((min_gap * SAMPLE_RATE as f32) as usize / SILENCE_FRAME_LEN).max(1);
+
+    let mut silences = Vec::new();
+    let mut silent_frames = 0;
+    for (i, frame) in samples.chunks(SILENCE_FRAME_LEN).enumerate() {
+        if rms(frame) < SILENCE_RMS {
+            silent_frames += 1;
+            continue;
+        }
+        if silent_frames >= gap_frames && silent_frames < i {
+            silences.push((i - silent_frames) * SILENCE_FRAME_LEN..i * SILENCE_FRAME_LEN);
+        }
+        silent_frames = 0;
+    }
+
+    silences
+}
+
+/// Splits 16kHz samples at silences lasting at least `min_gap` seconds.
+///
+/// Cuts land in the middle of each gap. Chunks shorter than `min_len`
+/// seconds are merged into their neighbour so brief noises don't become
+/// chunks of their own.
+pub fn split_on_silence(samples: &[f32], min_gap: f32, min_len: f32) -> Vec<&[f32]> {
+    split_ranges(samples, min_gap, min_len)
+        .into_iter()
+        .map(|r| &samples[r])
+        .collect()
+}
+
+/// Like `split_on_silence`, but returns each chunk's range of sample indices.
+pub fn split_ranges(samples: &[f32], min_gap: f32, min_len: f32) -> Vec<Range<usize>> {
+    let min_samples = (min_len * SAMPLE_RATE as f32) as usize;
+    let cuts = find_silences(samples, min_gap)
+        .into_iter()
+        .map(|gap| gap.start + gap.len() / 2);
+
+    // Turn cuts into ranges, merging any that are too short
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+    for end in cuts.chain(std::iter::once(samples.len())) {
+        match ranges.last_mut() {
+            Some(last) if last.len() < min_samples || end - start < min_samples => last.end = end,
+            _ => ranges.push(start..end),
+        }
+        start = end;
+    }
+
+    ranges
+}
+
+/// Root-mean-square level of a block of samples.
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes stereo `frames` (left, right) at 16kHz in the given format to a
+    /// temp file, returning its path.
+    fn write_fixture(
+        name: &str,
+        format: hound::SampleFormat,
+        bits: u16,
+        frames: &[(f32, f32)],
+    ) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("tx-test-{}-{name}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: bits,
+            sample_format: format,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        let full = (1i64 << (bits - 1)) as f32;
+        for &(l, r) in frames {
+            for s in [l, r] {
+                match format {
+                    hound::SampleFormat::Float => writer.write_sample(s).unwrap(),
+                    hound::SampleFormat::Int if bits == 8 => {
+                        writer.write_sample((s * full) as i8).unwrap()
+                    }
+                    hound::SampleFormat::Int => writer.write_sample((s * full) as i32).unwrap(),
+                }
+            }
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn read_wav_scales_every_depth() {
+        let frames = [(0.5, 0.5), (-0.5, -0.5), (0.5, -0.5)];
+        for (name, format, bits) in [
+            ("int8", hound::SampleFormat::Int, 8),
+            ("int16", hound::SampleFormat::Int, 16),
+            ("int24", hound::SampleFormat::Int, 24),
+            ("int32", hound::SampleFormat::Int, 32),
+            ("float32", hound::SampleFormat::Float, 32),
+        ] {
+            let path = write_fixture(name, format, bits, &frames);
+            let samples = read_wav(&path, false).unwrap();
+            std::fs::remove_file(&path).ok();
+            // Stereo is averaged to mono
+            let expected = [0.5, -0.5, 0.0];
+            assert_eq!(samples.len(), expected.len(), "{name}");
+            for (got, want) in samples.iter().zip(expected) {
+                assert!((got - want).abs() < 0.01, "{name}: {got} != {want}");
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a microphone"]
+    fn back_to_back_recordings_share_a_stop_flag() {
+        // As in --loop: one flag, left for Ctrl-C, across every recording
+        let stop = Arc::new(AtomicBool::new(false));
+        let opts = Options {
+            quiet: true,
+            stop: Some(Arc::clone(&stop)),
+            detached: true,
+            ..Options::default()
+        };
+        for _ in 0..2 {
+            let mut check = |_: &[f32]| true;
+            let watch = Watch {
+                window: Duration::from_millis(100),
+                every: Duration::from_millis(100),
+                after: Duration::from_millis(700),
+                check: &mut check,
+            };
+            let samples = record_until_enter(&opts, Some(watch)).unwrap();
+            assert!(!too_short(&samples, SAMPLE_RATE));
+            assert!(!stop.load(Ordering::Relaxed));
+        }
+    }
+
+    #[test]
+    fn mono_averages_any_channel_count() {
+        let collect = |data: &[f32], channels| mono(data, channels, |s| s).collect::<Vec<_>>();
+        assert_eq!(collect(&[0.1, 0.2, 0.3], 1), [0.1, 0.2, 0.3]);
+        assert_eq!(collect(&[1.0, 0.0, 0.5, 0.5], 2), [0.5, 0.5]);
+        assert_eq!(
+            collect(
+                &[0.6, 0.6, 0.6, 0.0, 0.0, 0.0, 1.2, 0.0, 0.0, 0.0, 0.0, 0.0],
+                6
+            ),
+            [0.3, 0.2]
+        );
+        let ints: Vec<f32> = mono(&[16384i16, -16384], 2, |s| s as f32 / 32768.0).collect();
+        assert_eq!(ints, [0.0]);
+    }
+
+    #[test]
+    fn too_short_at_the_boundary() {
+        let half_second = SAMPLE_RATE as usize / 2;
+        assert!(too_short(&[], SAMPLE_RATE));
+        assert!(too_short(&vec![0.0; half_second - 1], SAMPLE_RATE));
+        assert!(!too_short(&vec![0.0; half_second], SAMPLE_RATE));
+        assert!(!too_short(&vec![0.0; 24000], 48000));
+        assert!(too_short(&vec![0.0; 23999], 48000));
+    }
+
+    #[test]
+    fn resample_handles_tiny_inputs() {
+        for (from, to) in [(48000, 16000), (8000, 16000), (44100, 16000)] {
+            assert!(resample(&[], from, to).is_empty());
+            let one = resample(&[0.5], from, to);
+            assert!(!one.is_empty());
+            assert!(one.iter().all(|&s| s == 0.5));
+            let two = resample(&[0.25, 0.75], from, to);
+            assert!(!two.is_empty());
+            assert!(two.iter().all(|s| (0.25..=0.75).contains(s)));
+        }
+        assert!(resample(&[0.5], 0, 16000).is_empty());
+    }
+
+    #[test]
+    fn resample_scales_length_by_the_ratio() {
+        let second = vec![0.1; 48000];
+        assert_eq!(resample(&second, 48000, 16000).len(), 16000);
+        assert_eq!(resample(&second, 48000, 48000), second);
+        let ramp: Vec<f32> = (0..8000).map(|i| i as f32 / 8000.0).collect();
+        let up = resample(&ramp, 8000, 16000);
+        assert_eq!(up.len(), 16000);
+        assert!(up.windows(2).all(|w| w[0] <= w[1]));
+    }
+}
@@ -0,0 +1,86 @@
+//! Optional user configuration at `~/.config/tx/config.toml`.
+//!
+//! Every key is optional; command-line flags override config values.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Settings read from the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Length of generated transcript IDs (4-16, default 7)
+    pub id_length: Option<usize>,
+    /// Save each transcript to a text file (default true)
+    pub save_file: Option<bool>,
+    /// Store and show times in UTC instead of local time (default false)
+    pub utc: Option<bool>,
+    /// Encrypt the history database with SQLCipher, keyed by `TX_DB_KEY`
+    pub encrypt_history: Option<bool>,
+    /// Language for `--lang auto` clips too short to detect (default "en")
+    pub fallback_lang: Option<String>,
+    /// Keep at most this many unpinned, unarchived transcripts, deleting the
+    /// oldest after each save (default unlimited)
+    pub max_history: Option<usize>,
+    /// Key that stops recording: "space", "enter" (default) or one character
+    pub stop_key: Option<String>,
+    /// Words and phrases `--strip-filler` removes, replacing the built-in
+    /// English list (um, uh, er, ah, hmm, you know, ...)
+    pub filler_words: Option<Vec<String>>,
+    /// Extra attempts at opening the audio device when it fails, e.g. while
+    /// a Bluetooth mic wakes up (default 2; 0 gives up at once)
+    pub device_retries: Option<u32>,
+}
+
+/// Returns the path to the config file.
+pub fn config_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("Cannot determine config directory")?
+        .join("tx")
+        .join("config.toml"))
+}
+
+/// Data directory set by `--data-dir`, which beats `TX_DATA_DIR`.
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Points `data_dir` at `dir` for the rest of the process.
+pub fn set_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR.set(dir);
+}
+
+/// Returns the directory holding models, history and other state, creating
+/// it if needed: `--data-dir`, then `TX_DATA_DIR`, then `tx` in the
+/// platform's local data directory.
+pub fn data_dir() -> Result<PathBuf> {
+    let dir = match DATA_DIR.get() {
+        Some(dir) => dir.clone(),
+        None => match std::env::var_os("TX_DATA_DIR").filter(|d| !d.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::data_local_dir()
+                .context("Cannot determine local data directory")?
+                .join("tx"),
+        },
+    };
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create data directory {}", dir.display()))?;
+    Ok(dir)
+}
+
+impl Config {
+    /// Loads the config file, or defaults if it doesn't exist.
+    pub fn load() -> Result<Config> {
+        let path = config_path()?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
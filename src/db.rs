@@ -1,33 +1,189 @@
 //! SQLite database for transcript history.
 
-use anyhow::{Context, Result};
-use chrono::{DateTime, Local};
-use rusqlite::Connection;
-use std::path::PathBuf;
+use crate::config;
+use crate::transcribe::Segment;
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, FixedOffset, Local};
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::debug;
 
 /// A stored transcript record.
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Transcript {
     pub id: String,
     pub text: String,
-    pub timestamp: DateTime<Local>,
+    /// In the local timezone, whatever offset it was stored with
+    pub timestamp: DateTime<FixedOffset>,
     pub working_dir: String,
+    pub output_path: Option<String>,
+    /// Hidden from history unless explicitly requested
+    pub archived: bool,
+    /// WAV recording of the audio, if it was saved
+    pub audio_path: Option<String>,
+    /// Timed segments; empty for transcripts saved before these were stored
+    pub segments: Vec<Segment>,
+    /// Human-friendly name usable in place of the ID
+    pub alias: Option<String>,
+    /// Listed before unpinned transcripts in history
+    pub pinned: bool,
+    /// Language code Whisper decoded as; unknown for older transcripts
+    pub language: Option<String>,
+    /// Heuristic extractive summary, if `--summary` was given
+    pub summary: Option<String>,
+    /// Version of tx that saved it; "unknown" for older transcripts
+    pub tx_version: Option<String>,
+    /// File name of the model that produced it; "unknown" for older
+    /// transcripts
+    pub model: Option<String>,
+    /// How the audio was captured: "mic", "stdin" or "file:<path>", or
+    /// "merge:<id>,<id>..." for the output of `tx merge`
+    pub source: Option<String>,
+    /// Whisper's text before post-processing; the same as `text` when none
+    /// was applied
+    pub raw_text: String,
+    /// Free-form annotation set with `tx note`
+    pub note: Option<String>,
+}
+
+/// Columns selected for every `Transcript` query, in `from_row` order.
+const COLUMNS: &str = "id, text, timestamp, working_dir, output_path, archived, audio_path, segments, alias, pinned, \
+     language, summary, tx_version, model, source, raw_text, note";
+
+/// Schema changes applied after the initial table, in order.
+///
+/// `PRAGMA user_version` records how many have already run.
+const MIGRATIONS: &[&str] = &[
+    "ALTER TABLE transcripts ADD COLUMN output_path TEXT",
+    "ALTER TABLE transcripts ADD COLUMN archived INTEGER NOT NULL DEFAULT 0",
+    "ALTER TABLE transcripts ADD COLUMN audio_path TEXT;
+     ALTER TABLE transcripts ADD COLUMN segments TEXT;",
+    "ALTER TABLE transcripts ADD COLUMN alias TEXT;
+     CREATE UNIQUE INDEX transcripts_alias ON transcripts (alias);",
+    "ALTER TABLE transcripts ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0",
+    "ALTER TABLE transcripts ADD COLUMN language TEXT",
+    // Let newest-first listings (plain and pinned-first) stop after LIMIT
+    // rows instead of sorting the whole table
+    "CREATE INDEX transcripts_timestamp ON transcripts (timestamp);
+     CREATE INDEX transcripts_pinned_timestamp ON transcripts (pinned, timestamp);",
+    "ALTER TABLE transcripts ADD COLUMN summary TEXT",
+    "ALTER TABLE transcripts ADD COLUMN tx_version TEXT;
+     ALTER TABLE transcripts ADD COLUMN model TEXT;
+     UPDATE transcripts SET tx_version = 'unknown', model = 'unknown';",
+    // Before this, transcripts came from the mic unless --input was used
+    "ALTER TABLE transcripts ADD COLUMN source TEXT;
+     UPDATE transcripts SET source = 'mic';",
+    "ALTER TABLE transcripts ADD COLUMN raw_text TEXT;
+     UPDATE transcripts SET raw_text = text;",
+    "ALTER TABLE transcripts ADD COLUMN note TEXT",
+    // Stored offsets can differ (local vs --utc), so listings sort by the
+    // instant, not the string; index that instead of the raw text. (There
+    // is no text search yet, so no full-text index either.)
+    "DROP INDEX transcripts_timestamp;
+     DROP INDEX transcripts_pinned_timestamp;
+     CREATE INDEX transcripts_time ON transcripts (julianday(timestamp));
+     CREATE INDEX transcripts_pinned_time ON transcripts (pinned, julianday(timestamp));",
+];
+
+/// Which transcripts a listing includes.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Include archived transcripts
+    pub include_archived: bool,
+    /// Only transcripts at or after this time
+    pub since: Option<DateTime<Local>>,
+    /// Only transcripts before this time
+    pub until: Option<DateTime<Local>>,
+    /// Only transcripts recorded in this working directory
+    pub dir: Option<String>,
+    /// Sort pinned transcripts ahead of the rest, instead of purely by time
+    pub pinned_first: bool,
+}
+
+impl Filter {
+    /// Returns the SQL `ORDER BY` clause for this filter, newest first.
+    fn order_clause(&self) -> &'static str {
+        if self.pinned_first {
+            "ORDER BY pinned DESC, julianday(timestamp) DESC"
+        } else {
+            "ORDER BY julianday(timestamp) DESC"
+        }
+    }
+
+    /// Returns the SQL `WHERE` clause for this filter, with a `?` for each
+    /// of the values that follow it.
+    fn where_clause(&self) -> (String, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if !self.include_archived {
+            conditions.push("archived = 0");
+        }
+        // julianday() honors the stored UTC offsets, unlike string comparison
+        if let Some(since) = self.since {
+            conditions.push("julianday(timestamp) >= julianday(?)");
+            params.push(since.to_rfc3339());
+        }
+        if let Some(until) = self.until {
+            conditions.push("julianday(timestamp) < julianday(?)");
+            params.push(until.to_rfc3339());
+        }
+        if let Some(dir) = &self.dir {
+            conditions.push("working_dir = ?");
+            params.push(dir.clone());
+        }
+
+        if conditions.is_empty() {
+            (String::new(), params)
+        } else {
+            (format!("WHERE {}", conditions.join(" AND ")), params)
+        }
+    }
 }
 
 /// Returns the path to the database file.
 fn db_path() -> Result<PathBuf> {
-    let data_dir = dirs::data_local_dir()
-        .context("Cannot determine local data directory")?
-        .join("tx");
-    std::fs::create_dir_all(&data_dir)?;
-    Ok(data_dir.join("history.db"))
+    Ok(config::data_dir()?.join("history.db"))
+}
+
+/// Environment variable holding the history encryption passphrase.
+#[cfg(feature = "sqlcipher")]
+const KEY_VAR: &str = "TX_DB_KEY";
+
+/// The config file settings the database honors, read once by the caller.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Settings {
+    /// Encrypt with SQLCipher, keyed by `TX_DB_KEY` (`encrypt_history`)
+    pub encrypt: bool,
+    /// Keep at most this many unpinned, unarchived transcripts
+    /// (`max_history`)
+    pub max_history: Option<usize>,
+}
+
+impl From<&config::Config> for Settings {
+    fn from(config: &config::Config) -> Self {
+        Settings {
+            encrypt: config.encrypt_history.unwrap_or(false),
+            max_history: config.max_history,
+        }
+    }
 }
 
 /// Opens a connection to the database, creating it if needed.
-pub fn open() -> Result<Connection> {
+pub fn open(settings: &Settings) -> Result<Connection> {
     let path = db_path()?;
-    let conn = Connection::open(&path)?;
+    debug!(path = %path.display(), "opening history database");
+    let conn = if settings.encrypt {
+        open_encrypted(&path)?
+    } else {
+        Connection::open(&path)?
+    };
+    init(&conn)?;
+    Ok(conn)
+}
 
+/// Creates the table if needed and brings its schema up to date.
+fn init(conn: &Connection) -> Result<()> {
     conn.execute(
         "CREATE TABLE IF NOT EXISTS transcripts (
             id TEXT PRIMARY KEY,
@@ -38,11 +194,138 @@ pub fn open() -> Result<Connection> {
         [],
     )?;
 
+    migrate(conn)
+}
+
+/// Opens the database with the `TX_DB_KEY` passphrase, first encrypting it
+/// in place if it is still a plaintext database.
+#[cfg(feature = "sqlcipher")]
+fn open_encrypted(path: &Path) -> Result<Connection> {
+    let key = std::env::var(KEY_VAR)
+        .ok()
+        .filter(|key| !key.is_empty())
+        .with_context(|| format!("encrypt_history is enabled but {} is not set", KEY_VAR))?;
+
+    if is_plaintext(path)? {
+        encrypt_in_place(path, &key)?;
+    }
+
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "key", &key)?;
+    conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .with_context(|| format!("Cannot decrypt history database (wrong {}?)", KEY_VAR))?;
     Ok(conn)
 }
 
-/// Generates a short ID from the text and timestamp.
-fn generate_id(text: &str, timestamp: &DateTime<Local>) -> String {
+#[cfg(not(feature = "sqlcipher"))]
+fn open_encrypted(_path: &Path) -> Result<Connection> {
+    bail!("encrypt_history requires tx built with `--features sqlcipher`")
+}
+
+/// Returns true if `path` is an unencrypted SQLite database.
+#[cfg(feature = "sqlcipher")]
+fn is_plaintext(path: &Path) -> Result<bool> {
+    use std::io::Read;
+
+    let mut file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {}", path.display())),
+    };
+    let mut header = [0u8; 16];
+    let read = file.read(&mut header)?;
+    Ok(read == header.len() && &header == b"SQLite format 3\0")
+}
+
+/// Rewrites a plaintext database as an encrypted one, replacing the original.
+#[cfg(feature = "sqlcipher")]
+fn encrypt_in_place(path: &Path, key: &str) -> Result<()> {
+    let staging = path.with_extension("db.encrypting");
+    let _ = std::fs::remove_file(&staging);
+
+    let conn = Connection::open(path)?;
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    conn.execute(
+        "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+        (staging.to_string_lossy(), key),
+    )?;
+    conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))
+        .context("Failed to encrypt history database")?;
+    conn.pragma_update(
+        Some(rusqlite::DatabaseName::Attached("encrypted")),
+        "user_version",
+        version,
+    )?;
+    conn.execute("DETACH DATABASE encrypted", [])?;
+    drop(conn);
+
+    std::fs::rename(&staging, path).context("Failed to replace history database")?;
+    eprintln!("\x1b[90mEncrypted existing history database.\x1b[0m");
+    Ok(())
+}
+
+/// Checks that the database can take a write lock, without changing it.
+pub fn check_writable(conn: &Connection) -> Result<()> {
+    conn.execute_batch("BEGIN IMMEDIATE; ROLLBACK;")
+        .context("History database is not writable")
+}
+
+/// Runs any migrations newer than the database's recorded version.
+fn migrate(conn: &Connection) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, sql) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        debug!(migration = i + 1, "applying database migration");
+        conn.execute_batch(sql)
+            .with_context(|| format!("Failed to apply database migration {}", i + 1))?;
+        conn.pragma_update(None, "user_version", (i + 1) as i64)?;
+    }
+
+    Ok(())
+}
+
+/// Builds a `Transcript` from a row selected with `COLUMNS`.
+fn from_row(row: &Row) -> rusqlite::Result<Transcript> {
+    let timestamp_str: String = row.get(2)?;
+    let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
+        .map(|dt| dt.with_timezone(&Local).fixed_offset())
+        .unwrap_or_else(|_| Local::now().fixed_offset());
+
+    Ok(Transcript {
+        id: row.get(0)?,
+        text: row.get(1)?,
+        timestamp,
+        working_dir: row.get(3)?,
+        output_path: row.get(4)?,
+        archived: row.get(5)?,
+        audio_path: row.get(6)?,
+        segments: row
+            .get::<_, Option<String>>(7)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        alias: row.get(8)?,
+        pinned: row.get(9)?,
+        language: row.get(10)?,
+        summary: row.get(11)?,
+        tx_version: row.get(12)?,
+        model: row.get(13)?,
+        source: row.get(14)?,
+        raw_text: row.get(15)?,
+        note: row.get(16)?,
+    })
+}
+
+/// Default length of generated IDs.
+pub const DEFAULT_ID_LENGTH: usize = 7;
+
+/// Valid range for configured ID lengths (a 64-bit hash is 16 hex digits).
+pub const ID_LENGTH_RANGE: std::ops::RangeInclusive<usize> = 4..=16;
+
+/// Maximum number of candidates listed when an ID prefix is ambiguous.
+const MAX_AMBIGUOUS_SHOWN: usize = 5;
+
+/// Generates a short ID of `length` hex digits from the text and timestamp.
+pub fn generate_id(text: &str, timestamp: &DateTime<FixedOffset>, length: usize) -> String {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
@@ -51,41 +334,162 @@ fn generate_id(text: &str, timestamp: &DateTime<Local>) -> String {
     timestamp.to_rfc3339().hash(&mut hasher);
     let hash = hasher.finish();
 
-    format!("{:x}", hash)[..7].to_string()
+    let length = length.clamp(*ID_LENGTH_RANGE.start(), *ID_LENGTH_RANGE.end());
+    format!("{:016x}", hash)[..length].to_string()
 }
 
-/// Saves a transcript and returns its ID.
-pub fn save(conn: &Connection, text: &str, working_dir: &str) -> Result<String> {
-    let timestamp = Local::now();
-    let id = generate_id(text, &timestamp);
+/// A transcript about to be saved.
+#[derive(Debug)]
+pub struct NewTranscript<'a> {
+    /// ID from `generate_id`
+    pub id: &'a str,
+    pub text: &'a str,
+    /// Stored with its offset, local or UTC per `--utc`
+    pub timestamp: DateTime<FixedOffset>,
+    pub working_dir: &'a str,
+    /// File the transcript was written to, if any
+    pub output_path: Option<&'a str>,
+    /// WAV file the audio was written to, if any
+    pub audio_path: Option<&'a str>,
+    pub segments: &'a [Segment],
+    /// Language code Whisper decoded as
+    pub language: Option<&'a str>,
+    pub summary: Option<&'a str>,
+    /// File name of the model that produced it
+    pub model: Option<&'a str>,
+    /// "mic", "stdin", "file:<path>" or "merge:<id>,<id>..."
+    pub source: Option<&'a str>,
+    /// Whisper's text before post-processing, if any changed it
+    pub raw_text: Option<&'a str>,
+}
 
+/// Saves a transcript under its ID, stamped with this version of tx, then
+/// trims history to `max_history` transcripts if set.
+pub fn save(conn: &Connection, t: &NewTranscript, max_history: Option<usize>) -> Result<()> {
+    debug!(id = t.id, chars = t.text.len(), "saving transcript");
     conn.execute(
-        "INSERT OR REPLACE INTO transcripts (id, text, timestamp, working_dir) VALUES (?1, ?2, ?3, ?4)",
-        (&id, text, timestamp.to_rfc3339(), working_dir),
+        "INSERT OR REPLACE INTO transcripts
+            (id, text, timestamp, working_dir, output_path, audio_path, segments, language,
+             summary, tx_version, model, source, raw_text)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        (
+            t.id,
+            t.text,
+            t.timestamp.to_rfc3339(),
+            t.working_dir,
+            t.output_path,
+            t.audio_path,
+            serde_json::to_string(t.segments)?,
+            t.language,
+            t.summary,
+            env!("CARGO_PKG_VERSION"),
+            t.model,
+            t.source,
+            t.raw_text.unwrap_or(t.text),
+        ),
     )?;
 
-    Ok(id)
+    if let Some(max) = max_history {
+        trim(conn, max)?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the oldest transcripts beyond the newest `keep`, returning how
+/// many were removed. Pinned and archived transcripts are never deleted and
+/// don't count toward `keep`.
+fn trim(conn: &Connection, keep: usize) -> Result<usize> {
+    let deleted = conn.execute(
+        "DELETE FROM transcripts
+         WHERE pinned = 0 AND archived = 0 AND id NOT IN (
+             SELECT id FROM transcripts
+             WHERE pinned = 0 AND archived = 0
+             ORDER BY julianday(timestamp) DESC
+             LIMIT ?1
+         )",
+        [keep as i64],
+    )?;
+    if deleted > 0 {
+        debug!(deleted, keep, "trimmed history to max_history");
+    }
+    Ok(deleted)
+}
+
+/// Lists recent transcripts matching `filter`, at most `limit` of them, or
+/// all of them if `limit` is 0.
+pub fn list(conn: &Connection, limit: usize, filter: &Filter) -> Result<Vec<Transcript>> {
+    // SQLite treats a negative LIMIT as none; anything past i64::MAX (which
+    // rusqlite couldn't bind) is no limit in practice either
+    let limit = match limit {
+        0 => -1,
+        n => i64::try_from(n).unwrap_or(i64::MAX),
+    };
+    let (_, params) = filter.where_clause();
+    let mut stmt = conn.prepare(&list_query(filter))?;
+    let params = params
+        .iter()
+        .map(|p| p as &dyn rusqlite::ToSql)
+        .chain([&limit as &dyn rusqlite::ToSql]);
+
+    let rows = stmt.query_map(rusqlite::params_from_iter(params), from_row)?;
+
+    let mut transcripts = Vec::new();
+    for row in rows {
+        transcripts.push(row?);
+    }
+
+    Ok(transcripts)
+}
+
+/// The query `list` runs, with the limit after the filter's parameters.
+fn list_query(filter: &Filter) -> String {
+    format!(
+        "SELECT {} FROM transcripts {} {} LIMIT ?",
+        COLUMNS,
+        filter.where_clause().0,
+        filter.order_clause()
+    )
 }
 
-/// Lists recent transcripts.
-pub fn list(conn: &Connection, limit: usize) -> Result<Vec<Transcript>> {
+/// Counts transcripts matching `filter`.
+pub fn count(conn: &Connection, filter: &Filter) -> Result<usize> {
+    let (clause, params) = filter.where_clause();
+    let count: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM transcripts {}", clause),
+        rusqlite::params_from_iter(params),
+        |row| row.get(0),
+    )?;
+    Ok(count as usize)
+}
+
+/// Lists each working directory in history with its transcript count,
+/// most used first.
+pub fn dirs(conn: &Connection) -> Result<Vec<(String, usize)>> {
     let mut stmt = conn.prepare(
-        "SELECT id, text, timestamp, working_dir FROM transcripts ORDER BY timestamp DESC LIMIT ?1",
+        "SELECT working_dir, COUNT(*) FROM transcripts
+         GROUP BY working_dir ORDER BY COUNT(*) DESC, working_dir ASC",
     )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?;
 
-    let rows = stmt.query_map([limit], |row| {
-        let timestamp_str: String = row.get(2)?;
-        let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-            .map(|dt| dt.with_timezone(&Local))
-            .unwrap_or_else(|_| Local::now());
+    let mut dirs = Vec::new();
+    for row in rows {
+        dirs.push(row?);
+    }
 
-        Ok(Transcript {
-            id: row.get(0)?,
-            text: row.get(1)?,
-            timestamp,
-            working_dir: row.get(3)?,
-        })
-    })?;
+    Ok(dirs)
+}
+
+/// Lists unpinned transcripts from before `cutoff`, oldest first.
+pub fn unpinned_before(conn: &Connection, cutoff: DateTime<Local>) -> Result<Vec<Transcript>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM transcripts
+         WHERE pinned = 0 AND julianday(timestamp) < julianday(?1)
+         ORDER BY julianday(timestamp) ASC",
+        COLUMNS
+    ))?;
+
+    let rows = stmt.query_map([cutoff.to_rfc3339()], from_row)?;
 
     let mut transcripts = Vec::new();
     for row in rows {
@@ -95,29 +499,425 @@ pub fn list(conn: &Connection, limit: usize) -> Result<Vec<Transcript>> {
     Ok(transcripts)
 }
 
-/// Finds a transcript by ID prefix.
+/// Lists every transcript, oldest first.
+pub fn all(conn: &Connection) -> Result<Vec<Transcript>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM transcripts ORDER BY julianday(timestamp) ASC",
+        COLUMNS
+    ))?;
+
+    let rows = stmt.query_map([], from_row)?;
+
+    let mut transcripts = Vec::new();
+    for row in rows {
+        transcripts.push(row?);
+    }
+
+    Ok(transcripts)
+}
+
+/// Finds a transcript by alias or ID prefix.
+///
+/// An exact ID or alias match always wins. Otherwise the prefix must match
+/// exactly one ID; an ambiguous prefix is an error listing the candidates.
 pub fn find_by_prefix(conn: &Connection, prefix: &str) -> Result<Option<Transcript>> {
-    let mut stmt = conn.prepare(
-        "SELECT id, text, timestamp, working_dir FROM transcripts WHERE id LIKE ?1 || '%' LIMIT 1",
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM transcripts WHERE id = ?1 OR alias = ?1",
+        COLUMNS
+    ))?;
+    if let Some(t) = stmt.query_row([prefix], from_row).optional()? {
+        return Ok(Some(t));
+    }
+
+    // Compared as text rather than with LIKE, where a `%` or `_` typed as
+    // part of the prefix would match anything; case still doesn't matter
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM transcripts WHERE lower(substr(id, 1, length(?1))) = lower(?1)
+         ORDER BY julianday(timestamp) DESC LIMIT ?2",
+        COLUMNS
+    ))?;
+    let mut matches = stmt
+        .query_map((prefix, MAX_AMBIGUOUS_SHOWN + 1), from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    if matches.len() > 1 {
+        let shown: Vec<&str> = matches
+            .iter()
+            .take(MAX_AMBIGUOUS_SHOWN)
+            .map(|t| t.id.as_str())
+            .collect();
+        let more = if matches.len() > MAX_AMBIGUOUS_SHOWN {
+            ", ..."
+        } else {
+            ""
+        };
+        bail!(
+            "ID prefix '{}' is ambiguous: matches {}{}",
+            prefix,
+            shown.join(", "),
+            more
+        );
+    }
+
+    Ok(matches.pop())
+}
+
+impl Transcript {
+    /// Strips filesystem layout from the path fields, keeping only the
+    /// final path component of `working_dir` and `output_path`.
+    pub fn redact_paths(&mut self) {
+        self.working_dir = basename(&self.working_dir);
+        self.output_path = self.output_path.as_deref().map(basename);
+    }
+}
+
+/// Returns the last component of a path, or the input if it has none.
+fn basename(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Sets or clears the archived flag on a transcript by its full ID.
+pub fn set_archived(conn: &Connection, id: &str, archived: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE transcripts SET archived = ?1 WHERE id = ?2",
+        (archived, id),
+    )?;
+    Ok(())
+}
+
+/// Appends text to a transcript on a new line, and `raw_text` likewise to
+/// its unprocessed text.
+pub fn append(conn: &Connection, id: &str, text: &str, raw_text: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE transcripts SET text = text || char(10) || ?1,
+             raw_text = raw_text || char(10) || ?2
+         WHERE id = ?3",
+        (text, raw_text, id),
     )?;
+    Ok(())
+}
+
+/// Sets or clears the note on a transcript by its full ID.
+pub fn set_note(conn: &Connection, id: &str, note: Option<&str>) -> Result<()> {
+    conn.execute("UPDATE transcripts SET note = ?1 WHERE id = ?2", (note, id))?;
+    Ok(())
+}
+
+/// Pins or unpins a transcript by its full ID.
+pub fn set_pinned(conn: &Connection, id: &str, pinned: bool) -> Result<()> {
+    conn.execute(
+        "UPDATE transcripts SET pinned = ?1 WHERE id = ?2",
+        (pinned, id),
+    )?;
+    Ok(())
+}
+
+/// Gives a transcript an alias, replacing any it had.
+///
+/// Aliases must be unique, and can't consist only of hex digits since they
+/// would then be mistaken for ID prefixes.
+pub fn set_alias(conn: &Connection, id: &str, alias: &str) -> Result<()> {
+    if alias.is_empty() || alias.chars().any(char::is_whitespace) {
+        bail!("Alias '{}' must be non-empty and contain no spaces", alias);
+    }
+    if alias.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!(
+            "Alias '{}' looks like a transcript ID; include a non-hex character",
+            alias
+        );
+    }
+
+    let owner: Option<String> = conn
+        .query_row(
+            "SELECT id FROM transcripts WHERE alias = ?1",
+            [alias],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(owner) = owner.filter(|owner| owner != id) {
+        bail!("Alias '{}' is already used by {}", alias, owner);
+    }
+
+    conn.execute(
+        "UPDATE transcripts SET alias = ?1 WHERE id = ?2",
+        (alias, id),
+    )?;
+    Ok(())
+}
+
+/// Deletes a transcript by its full ID.
+pub fn delete(conn: &Connection, id: &str) -> Result<()> {
+    debug!(id, "deleting transcript");
+    conn.execute("DELETE FROM transcripts WHERE id = ?1", [id])?;
+    Ok(())
+}
+
+/// Deletes transcripts by full ID in one transaction, returning how many
+/// were removed.
+pub fn delete_many(conn: &mut Connection, ids: &[&str]) -> Result<usize> {
+    debug!(count = ids.len(), "deleting transcripts");
+    let tx = conn.transaction()?;
+    let mut deleted = 0;
+    {
+        let mut stmt = tx.prepare("DELETE FROM transcripts WHERE id = ?1")?;
+        for id in ids {
+            deleted += stmt.execute([id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        init(&conn).unwrap();
+        conn
+    }
+
+    /// Saves a transcript whose text is its ID.
+    fn add(conn: &Connection, id: &str, timestamp: &str, max_history: Option<usize>) {
+        let transcript = NewTranscript {
+            id,
+            text: id,
+            timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap(),
+            working_dir: "/tmp",
+            output_path: None,
+            audio_path: None,
+            segments: &[],
+            language: None,
+            summary: None,
+            model: None,
+            source: None,
+            raw_text: None,
+        };
+        save(conn, &transcript, max_history).unwrap();
+    }
+
+    fn ids(transcripts: &[Transcript]) -> Vec<&str> {
+        transcripts.iter().map(|t| t.id.as_str()).collect()
+    }
+
+    /// As strings these sort c, b, a; as instants they run a, b, c.
+    fn add_mixed_offsets(conn: &Connection, max_history: Option<usize>) {
+        add(conn, "aaaa", "2024-01-01T10:00:00+02:00", max_history); // 08:00Z
+        add(conn, "bbbb", "2024-01-01T09:00:00+00:00", max_history); // 09:00Z
+        add(conn, "cccc", "2024-01-01T05:00:00-05:00", max_history); // 10:00Z
+    }
+
+    /// Three transcripts a day apart: "old", "mid" (archived) and "new"
+    /// (pinned, in /work).
+    fn filter_fixture() -> Connection {
+        let conn = memory_db();
+        add(&conn, "old", "2024-01-01T12:00:00+00:00", None);
+        add(&conn, "mid", "2024-01-02T12:00:00+00:00", None);
+        add(&conn, "new", "2024-01-03T12:00:00+00:00", None);
+        set_archived(&conn, "mid", true).unwrap();
+        set_pinned(&conn, "old", true).unwrap();
+        conn.execute(
+            "UPDATE transcripts SET working_dir = '/work' WHERE id = 'new'",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    fn listed(conn: &Connection, filter: Filter) -> Vec<String> {
+        let found = list(conn, 10, &filter).unwrap();
+        assert_eq!(count(conn, &filter).unwrap(), found.len());
+        found.into_iter().map(|t| t.id).collect()
+    }
+
+    #[test]
+    fn filter_hides_archived_unless_asked() {
+        let conn = filter_fixture();
+        assert_eq!(listed(&conn, Filter::default()), ["new", "old"]);
+        let all = Filter {
+            include_archived: true,
+            ..Filter::default()
+        };
+        assert_eq!(listed(&conn, all), ["new", "mid", "old"]);
+    }
+
+    #[test]
+    fn filter_by_time_and_dir() {
+        let conn = filter_fixture();
+        let day = |d: u32| {
+            chrono::TimeZone::with_ymd_and_hms(&chrono::Utc, 2024, 1, d, 0, 0, 0)
+                .unwrap()
+                .with_timezone(&Local)
+        };
+        let since = Filter {
+            include_archived: true,
+            since: Some(day(2)),
+            ..Filter::default()
+        };
+        assert_eq!(listed(&conn, since), ["new", "mid"]);
+        // --until is exclusive
+        let until = Filter {
+            include_archived: true,
+            until: Some(day(2)),
+            ..Filter::default()
+        };
+        assert_eq!(listed(&conn, until), ["old"]);
+        let dir = Filter {
+            dir: Some("/work".to_string()),
+            ..Filter::default()
+        };
+        assert_eq!(listed(&conn, dir), ["new"]);
+        // Bound, not spliced into the SQL
+        let quoted = Filter {
+            dir: Some("/work' OR '1'='1".to_string()),
+            ..Filter::default()
+        };
+        assert!(listed(&conn, quoted).is_empty());
+    }
+
+    #[test]
+    fn filter_can_list_pinned_first() {
+        let conn = filter_fixture();
+        let pinned = Filter {
+            pinned_first: true,
+            ..Filter::default()
+        };
+        assert_eq!(listed(&conn, pinned), ["old", "new"]);
+    }
+
+    #[test]
+    fn list_limit_zero_lists_everything() {
+        let conn = memory_db();
+        for day in 1..=5 {
+            add(
+                &conn,
+                &format!("t{day}"),
+                &format!("2024-01-0{day}T12:00:00+00:00"),
+                None,
+            );
+        }
+        assert_eq!(list(&conn, 0, &Filter::default()).unwrap().len(), 5);
+        assert_eq!(list(&conn, 2, &Filter::default()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn ids_are_hex_of_the_clamped_length() {
+        let timestamp = DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap();
+        let id = generate_id("hello", &timestamp, DEFAULT_ID_LENGTH);
+        assert_eq!(id.len(), DEFAULT_ID_LENGTH);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(id, generate_id("hello", &timestamp, DEFAULT_ID_LENGTH));
+        assert_ne!(id, generate_id("hello!", &timestamp, DEFAULT_ID_LENGTH));
+
+        // Longer IDs extend shorter ones, so old prefixes keep matching
+        let long = generate_id("hello", &timestamp, 12);
+        assert!(long.starts_with(&id));
+        assert_eq!(generate_id("hello", &timestamp, 1).len(), 4);
+        assert_eq!(generate_id("hello", &timestamp, 99).len(), 16);
+    }
+
+    #[test]
+    fn thousands_of_ids_stay_unique() {
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap();
+        let ids: std::collections::HashSet<String> = (0..5_000)
+            .map(|i| {
+                let timestamp = start + chrono::Duration::seconds(i);
+                generate_id("same words", &timestamp, 12)
+            })
+            .collect();
+        assert_eq!(ids.len(), 5_000);
+    }
+
+    #[test]
+    fn prefixes_find_one_transcript() {
+        let conn = memory_db();
+        add(&conn, "abc1234", "2024-01-01T00:00:00+00:00", None);
+        add(&conn, "abd5678", "2024-01-02T00:00:00+00:00", None);
+        set_alias(&conn, "abd5678", "groceries").unwrap();
+
+        assert_eq!(find_by_prefix(&conn, "abc").unwrap().unwrap().id, "abc1234");
+        assert_eq!(
+            find_by_prefix(&conn, "abd5678").unwrap().unwrap().id,
+            "abd5678"
+        );
+        assert_eq!(
+            find_by_prefix(&conn, "groceries").unwrap().unwrap().id,
+            "abd5678"
+        );
+        assert!(find_by_prefix(&conn, "fff").unwrap().is_none());
+
+        let error = find_by_prefix(&conn, "a").unwrap_err().to_string();
+        assert!(error.contains("ambiguous"), "{}", error);
+        assert!(
+            error.contains("abc1234") && error.contains("abd5678"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn prefix_wildcards_match_literally() {
+        let conn = memory_db();
+        add(&conn, "abc1234", "2024-01-01T00:00:00+00:00", None);
+
+        assert_eq!(find_by_prefix(&conn, "ABC").unwrap().unwrap().id, "abc1234");
+        assert!(find_by_prefix(&conn, "%").unwrap().is_none());
+        assert!(find_by_prefix(&conn, "a_c").unwrap().is_none());
+    }
+
+    #[test]
+    fn mixed_offsets_sort_by_time() {
+        let conn = memory_db();
+        add_mixed_offsets(&conn, None);
+
+        let newest_first = list(&conn, 10, &Filter::default()).unwrap();
+        assert_eq!(ids(&newest_first), ["cccc", "bbbb", "aaaa"]);
+        assert_eq!(ids(&all(&conn).unwrap()), ["aaaa", "bbbb", "cccc"]);
+    }
+
+    #[test]
+    fn large_history_lists_through_the_index() {
+        let mut conn = memory_db();
+        let start = DateTime::parse_from_rfc3339("2024-01-01T00:00:00+00:00").unwrap();
+        let tx = conn.transaction().unwrap();
+        for i in 0..20_000 {
+            // Alternate offsets so string order and time order disagree
+            let offset = FixedOffset::east_opt(if i % 2 == 0 { 0 } else { 7200 }).unwrap();
+            let timestamp = (start + chrono::Duration::minutes(i)).with_timezone(&offset);
+            add(&tx, &format!("{:05}", i), &timestamp.to_rfc3339(), None);
+        }
+        tx.commit().unwrap();
+
+        for pinned_first in [false, true] {
+            let filter = Filter {
+                pinned_first,
+                ..Filter::default()
+            };
+            let plan: String = conn
+                .query_row(
+                    &format!("EXPLAIN QUERY PLAN {}", list_query(&filter)),
+                    [10],
+                    |row| row.get(3),
+                )
+                .unwrap();
+            assert!(plan.contains("USING INDEX"), "full scan: {}", plan);
+            assert!(!plan.contains("TEMP B-TREE"), "sorts: {}", plan);
+
+            let newest = list(&conn, 3, &filter).unwrap();
+            assert_eq!(ids(&newest), ["19999", "19998", "19997"]);
+        }
+        assert_eq!(list(&conn, 0, &Filter::default()).unwrap().len(), 20_000);
+    }
+
+    #[test]
+    fn mixed_offsets_trim_the_oldest() {
+        let conn = memory_db();
+        add_mixed_offsets(&conn, Some(2));
 
-    let mut rows = stmt.query_map([prefix], |row| {
-        let timestamp_str: String = row.get(2)?;
-        let timestamp = DateTime::parse_from_rfc3339(&timestamp_str)
-            .map(|dt| dt.with_timezone(&Local))
-            .unwrap_or_else(|_| Local::now());
-
-        Ok(Transcript {
-            id: row.get(0)?,
-            text: row.get(1)?,
-            timestamp,
-            working_dir: row.get(3)?,
-        })
-    })?;
-
-    match rows.next() {
-        Some(Ok(t)) => Ok(Some(t)),
-        Some(Err(e)) => Err(e.into()),
-        None => Ok(None),
+        assert_eq!(ids(&all(&conn).unwrap()), ["bbbb", "cccc"]);
     }
 }
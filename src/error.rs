@@ -0,0 +1,108 @@
+//! Exit codes that let scripts tell failures apart.
+//!
+//! | Code | Meaning                                        |
+//! |------|------------------------------------------------|
+//! | 1    | Any other error                                |
+//! | 2    | No audio was recorded (or the file was silent) |
+//! | 3    | No usable audio device                         |
+//! | 4    | The model couldn't be found, fetched or loaded |
+//! | 5    | Whisper produced no text                       |
+
+use std::fmt;
+
+/// Process exit codes for failures a caller may want to handle differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code {
+    General = 1,
+    NoAudio = 2,
+    NoDevice = 3,
+    Model = 4,
+    Empty = 5,
+}
+
+/// An error tagged with the exit code it should produce. It displays exactly
+/// like the error it wraps, so tagging never changes what the user sees.
+#[derive(Debug)]
+struct Tagged {
+    code: Code,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Tagged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+// The wrapped error's causes are this one's, so `{:#}` and `{:?}` still
+// show the whole chain
+impl std::error::Error for Tagged {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Attaches an exit code to the error in a `Result`.
+pub trait ResultExt<T> {
+    fn code(self, code: Code) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
+    fn code(self, code: Code) -> anyhow::Result<T> {
+        self.map_err(|e| {
+            anyhow::Error::new(Tagged {
+                code,
+                error: e.into(),
+            })
+        })
+    }
+}
+
+/// A new error with `message`, tagged with `code`.
+pub fn tagged(code: Code, message: &str) -> anyhow::Error {
+    anyhow::Error::new(Tagged {
+        code,
+        error: anyhow::anyhow!(message.to_string()),
+    })
+}
+
+/// Returns the exit code for an error, or `General` if it was never tagged.
+pub fn code_of(error: &anyhow::Error) -> Code {
+    error
+        .downcast_ref::<Tagged>()
+        .map_or(Code::General, |t| t.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn failing() -> anyhow::Result<()> {
+        Err(std::io::Error::other("connection reset"))
+            .context("Failed to connect to Hugging Face")
+            .code(Code::Model)
+    }
+
+    #[test]
+    fn tagging_keeps_the_cause_chain() {
+        let e = failing().unwrap_err();
+        assert_eq!(
+            format!("{:#}", e),
+            "Failed to connect to Hugging Face: connection reset"
+        );
+        assert!(format!("{:?}", e).contains("connection reset"));
+        assert_eq!(e.chain().count(), 2);
+    }
+
+    #[test]
+    fn codes_survive_more_context() {
+        let e = failing().context("Download failed").unwrap_err();
+        assert_eq!(code_of(&e), Code::Model);
+        assert_eq!(
+            format!("{:#}", e),
+            "Download failed: Failed to connect to Hugging Face: connection reset"
+        );
+        assert_eq!(code_of(&anyhow::anyhow!("plain")), Code::General);
+    }
+}
@@ -3,16 +3,48 @@
 //! Start talking, hit Enter, get text.
 
 mod audio;
+mod config;
 mod db;
+mod error;
 mod model;
 mod output;
+mod text;
 mod transcribe;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, Utc};
 use clap::{Parser, Subcommand};
-use std::io::{self, Write};
+use error::Code;
+use std::io::{self, IsTerminal, Write};
 use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Shortest chunk `--split-on-silence` will produce, in seconds.
+const MIN_SPLIT_SECS: f32 = 1.0;
+
+/// Pause that separates `--lang-per-segment` pieces, in seconds.
+const LANG_PIECE_GAP: f32 = 0.5;
+
+/// Shortest `--lang-per-segment` piece, in seconds; kept above the 2s
+/// language detection needs.
+const LANG_PIECE_SECS: f32 = 3.0;
+
+/// How much recent audio `--stop-phrase` listens to on each check.
+const STOP_PHRASE_WINDOW: Duration = Duration::from_secs(3);
+
+/// How often `--stop-phrase` checks the recording.
+const STOP_PHRASE_EVERY: Duration = Duration::from_secs(1);
+
+/// How much recent audio `--live` transcribes for its tentative line. It
+/// only shows the end of the text, and more would slow every update.
+const LIVE_WINDOW: Duration = Duration::from_secs(30);
+
+/// How often `--live` updates; also the least audio it transcribes, which
+/// keeps `--lang auto` above the shortest clip it can detect.
+const LIVE_EVERY: Duration = Duration::from_secs(2);
 
 #[derive(Parser)]
 #[command(name = "tx")]
@@ -23,9 +55,20 @@ struct Cli {
     command: Option<Command>,
 
     /// Output directory for transcription files
-    #[arg(short, long, default_value = "/tmp", global = true)]
+    #[arg(
+        short,
+        long,
+        env = "TX_OUTPUT_DIR",
+        default_value = "/tmp",
+        global = true
+    )]
     output_dir: PathBuf,
 
+    /// Keep models and history here instead of the platform data directory
+    /// (also TX_DATA_DIR)
+    #[arg(long, value_name = "DIR", global = true)]
+    data_dir: Option<PathBuf>,
+
     /// Quiet mode (text only to stdout)
     #[arg(short, long, global = true)]
     quiet: bool,
@@ -33,6 +76,276 @@ struct Cli {
     /// Skip copying to clipboard
     #[arg(long, global = true)]
     no_clip: bool,
+
+    /// Store and show times in UTC instead of local time [config: utc]
+    #[arg(long, global = true)]
+    utc: bool,
+
+    /// Print extra diagnostics on stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Don't show a progress bar while downloading a model (implied when
+    /// stderr isn't a terminal, which gets a line every 10% instead)
+    #[arg(long, global = true)]
+    no_progress: bool,
+
+    /// Spoken language code, "auto" to detect, or "none" to leave it to the
+    /// model (see `tx languages`)
+    #[arg(long, default_value = "en", global = true, value_parser = transcribe::parse_language)]
+    lang: String,
+
+    /// In quiet mode, don't print a trailing newline (like `echo -n`)
+    #[arg(short = 'n', long)]
+    no_newline: bool,
+
+    /// Print the result (or error) as a single JSON object on stdout
+    #[arg(long, conflicts_with = "split_on_silence")]
+    json: bool,
+
+    /// Don't save a text file [config: save_file = false]
+    #[arg(long, overrides_with = "save_file")]
+    no_file: bool,
+
+    /// Save a text file even if the config disables it
+    #[arg(long, overrides_with = "no_file")]
+    save_file: bool,
+
+    /// Write the transcript to exactly this file instead of a timestamped
+    /// one in --output-dir
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["no_file", "append_to", "split_on_silence", "repeat"]
+    )]
+    output_file: Option<PathBuf>,
+
+    /// Replace --output-file if it already exists
+    #[arg(long, requires = "output_file")]
+    overwrite: bool,
+
+    /// How to lay out the printed transcript (not the saved text)
+    #[arg(long, value_enum, default_value_t = output::Layout::Plain)]
+    display: output::Layout,
+
+    /// With --display lines or timestamps, join segments less than this far
+    /// apart so sentences aren't split across lines
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    max_segment_gap: Option<Duration>,
+
+    /// Color less certain words (yellow, red) in the printed transcript
+    #[arg(long)]
+    show_confidence: bool,
+
+    /// Print Whisper's segments, with word timings and confidence, as a JSON
+    /// array on stderr; stdout is unchanged
+    #[arg(long)]
+    debug_segments: bool,
+
+    /// Cut the printed transcript after N characters; the clipboard, files
+    /// and history still get all of it
+    #[arg(long, value_name = "N")]
+    display_max: Option<usize>,
+
+    /// Format of saved transcript files
+    #[arg(long, value_enum, default_value_t = output::FileFormat::Txt)]
+    format: output::FileFormat,
+
+    /// Write saved files with CRLF line endings (default on Windows)
+    #[arg(long)]
+    crlf: bool,
+
+    /// Start saved files with a UTF-8 byte order mark
+    #[arg(long)]
+    bom: bool,
+
+    /// Copy to clipboard as a "> " quote
+    #[arg(long)]
+    quote: bool,
+
+    /// Copy the new transcript's ID to the clipboard instead of its text
+    #[arg(long, conflicts_with_all = ["quote", "no_clip"])]
+    copy_id: bool,
+
+    /// Also copy through the terminal with an OSC 52 escape, which reaches
+    /// your local clipboard over SSH in terminals that support it
+    #[arg(long)]
+    osc52: bool,
+
+    /// Add the new text to an existing transcript (and its text file)
+    /// instead of creating a new one
+    #[arg(long, value_name = "ID")]
+    append_to: Option<String>,
+
+    /// Also append the transcript to this log file
+    #[arg(long, value_name = "FILE")]
+    append_log: Option<PathBuf>,
+
+    /// Also pipe the transcript to this shell command
+    #[arg(long, value_name = "COMMAND")]
+    exec: Option<String>,
+
+    /// Give up with an error if recording and transcription together take
+    /// longer than this
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    timeout: Option<Duration>,
+
+    /// Count down this many seconds before recording starts
+    #[arg(long, value_name = "SECS", value_parser = parse_secs)]
+    delay: Option<Duration>,
+
+    /// Record from this input device: its full name, or a unique part of it
+    /// (see `tx doctor` for the list). Repeat to mix several together
+    #[arg(long, value_name = "NAME", conflicts_with = "input")]
+    device: Vec<String>,
+
+    /// Play sound (--beep cues, tx play, show --play) on this output device:
+    /// its full name or a unique part (see `tx doctor`). Falls back to the
+    /// default output, with a warning, if it isn't found
+    #[arg(long, value_name = "NAME", global = true)]
+    output_device: Option<String>,
+
+    /// Record system audio from a loopback/monitor input instead of the mic
+    #[arg(long, conflicts_with_all = ["input", "device"])]
+    loopback: bool,
+
+    /// Stop recording on this key instead of Enter: "space" or a single
+    /// character, read straight from the terminal [config: stop_key]
+    #[arg(long, value_name = "KEY", conflicts_with = "input")]
+    stop_key: Option<String>,
+
+    /// Beep when recording starts (after any --delay) and when it stops
+    #[arg(long, conflicts_with = "input")]
+    beep: bool,
+
+    /// Stop recording when you say this phrase (matched ignoring case and
+    /// punctuation, and removed from the transcript)
+    #[arg(long, value_name = "PHRASE", conflicts_with = "input")]
+    stop_phrase: Option<String>,
+
+    /// While recording, keep a tentative transcript updated on stderr. It
+    /// is redone every few seconds, so earlier words may change
+    #[arg(long, conflicts_with = "input")]
+    live: bool,
+
+    /// Transcribe this WAV file instead of recording, or raw 32-bit float
+    /// mono samples from stdin with "-". Repeat to transcribe several files
+    /// in turn with one model load
+    #[arg(long, value_name = "FILE", conflicts_with = "repeat")]
+    input: Vec<PathBuf>,
+
+    /// With several --input files, carry on past one that fails instead of
+    /// stopping there; tx still exits with an error if any failed
+    #[arg(long, requires = "input")]
+    keep_going: bool,
+
+    /// Write a row per --input file to this report as each one finishes:
+    /// JSON Lines, or CSV if the name ends in .csv
+    #[arg(long, value_name = "PATH", requires = "input")]
+    report: Option<PathBuf>,
+
+    /// Transcribe the WAV file whose path is on the clipboard, e.g. after
+    /// copying it in a file manager
+    #[arg(long, conflicts_with_all = ["input", "repeat", "live"])]
+    from_clip: bool,
+
+    /// Sample rate of raw stdin input [default: 16000]
+    #[arg(long, value_name = "HZ", requires = "input", value_parser = clap::value_parser!(u32).range(1..))]
+    input_rate: Option<u32>,
+
+    /// Fail instead of resampling input that isn't already 16kHz
+    #[arg(long, requires = "input")]
+    no_resample: bool,
+
+    /// Multiply input levels by this factor, for quiet microphones
+    #[arg(long, value_name = "FACTOR", value_parser = parse_gain)]
+    input_gain: Option<f32>,
+
+    /// Keep only the first N words (history still stores the full text)
+    #[arg(long, value_name = "N")]
+    max_words: Option<usize>,
+
+    /// Keep only the first sentence (history still stores the full text)
+    #[arg(long)]
+    first_sentence: bool,
+
+    /// If Whisper returns no text, retry once at a higher temperature
+    #[arg(long)]
+    retry_empty: bool,
+
+    /// Exit 0 with empty output when nothing was transcribed, instead of
+    /// failing (under --json, print a transcript with empty text)
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Capitalize sentences and add missing terminal punctuation
+    #[arg(long)]
+    tidy: bool,
+
+    /// Remove filler words like "um", "uh" and "you know" (the list can be
+    /// set with filler_words in the config)
+    #[arg(long)]
+    strip_filler: bool,
+
+    /// Store a short extractive summary (a word-frequency heuristic, not AI)
+    /// with the transcript, shown in history
+    #[arg(long)]
+    summary: bool,
+
+    /// Length of generated transcript IDs [config: id_length, default: 7]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(4..=16))]
+    id_length: Option<u8>,
+
+    /// Also write the transcript to this named pipe (skipped if no reader)
+    #[arg(long, value_name = "PATH")]
+    fifo: Option<PathBuf>,
+
+    /// Show the transcript as a desktop notification (warns if unavailable)
+    #[arg(long)]
+    notify: bool,
+
+    /// Signal when transcription finishes: bell, flash, notify, or several
+    /// separated by commas
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "KIND")]
+    alert: Vec<output::Alert>,
+
+    /// Also save the recording as a WAV file next to the transcript
+    #[arg(long)]
+    save_audio: bool,
+
+    /// Sample format for --save-audio: 16 (PCM) or f32 (float)
+    #[arg(long, value_enum, default_value_t = audio::WavDepth::Pcm16, requires = "save_audio")]
+    wav_bitdepth: audio::WavDepth,
+
+    /// Start a "Speaker ?:" line at pauses longer than this (a heuristic,
+    /// not real speaker detection)
+    #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "1.5")]
+    label_turns: Option<f32>,
+
+    /// Experimental: with --lang auto, detect the language separately for
+    /// each stretch of speech between pauses, for recordings that switch
+    /// languages part way
+    #[arg(long)]
+    lang_per_segment: bool,
+
+    /// Split into separate transcripts at pauses longer than this
+    #[arg(long, value_name = "SECS")]
+    split_on_silence: Option<f32>,
+
+    /// After each transcript, start a new recording; Ctrl-C or an empty
+    /// recording ends the loop
+    #[arg(long = "loop", conflicts_with = "json")]
+    repeat: bool,
+
+    /// Show what the first-run model download would fetch, then exit
+    /// without downloading or recording
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Re-download the default model before recording, replacing the
+    /// existing file once the new one is complete
+    #[arg(long)]
+    force_download: bool,
 }
 
 #[derive(Subcommand)]
@@ -40,184 +353,2606 @@ enum Command {
     /// Show transcript history
     #[command(alias = "log")]
     History {
-        /// Number of entries to show
+        /// Number of entries to show (0 for all)
         #[arg(short, long, default_value = "10")]
         limit: usize,
+
+        /// Include archived transcripts
+        #[arg(long)]
+        all: bool,
+
+        /// Only transcripts from this time on: a date (2024-01-01), an
+        /// RFC 3339 time, or a relative age like 7d, 12h or 2w
+        #[arg(long, value_name = "WHEN", value_parser = parse_when)]
+        since: Option<DateTime<Local>>,
+
+        /// Only transcripts before this time (same forms as --since)
+        #[arg(long, value_name = "WHEN", value_parser = parse_when)]
+        until: Option<DateTime<Local>>,
+
+        /// Only transcripts recorded in this directory (see `tx dirs`)
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+
+        /// Only transcripts recorded in the current directory
+        #[arg(long, conflicts_with = "dir")]
+        here: bool,
+
+        /// Print just the number of matching transcripts
+        #[arg(long)]
+        count: bool,
+
+        /// Characters of text to preview per entry [default: fit the
+        /// terminal width, or 60 when not a terminal]
+        #[arg(long, value_name = "N")]
+        preview_len: Option<usize>,
     },
 
+    /// List the working directories seen in history, most used first
+    Dirs,
+
     /// Show a transcript by ID
     Show {
-        /// Transcript ID (or prefix)
+        /// Transcript ID (or prefix, or alias)
+        id: String,
+
+        /// Play the saved audio, printing each word as it is spoken
+        #[arg(long)]
+        play: bool,
+
+        /// Print Whisper's text from before --tidy, --strip-filler and other
+        /// post-processing
+        #[arg(long, conflicts_with = "play")]
+        raw: bool,
+    },
+
+    /// Play a transcript's saved audio (from --save-audio); Ctrl-C stops
+    Play {
+        /// Transcript ID (or prefix, or alias)
         id: String,
     },
 
     /// Copy a transcript to clipboard
     Copy {
-        /// Transcript ID (or prefix)
-        id: String,
+        /// Transcript ID (or prefix, or alias)
+        #[arg(required_unless_present = "last", conflicts_with = "last")]
+        id: Option<String>,
+
+        /// Copy the N most recent transcripts joined together, oldest first
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        last: Option<u32>,
+
+        /// Separator placed between transcripts with --last
+        #[arg(long, default_value = "\n", requires = "last")]
+        sep: String,
     },
 
     /// Show the most recent transcript
     Last,
-}
 
-fn main() {
-    if let Err(e) = run() {
-        eprintln!("Error: {:#}", e);
-        process::exit(1);
-    }
+    /// Join several transcripts, in the order given, into a new one
+    Merge {
+        /// Transcript IDs (or prefixes, or aliases)
+        #[arg(required = true, num_args = 2..)]
+        ids: Vec<String>,
+
+        /// Separator placed between the transcripts
+        #[arg(long, default_value = "\n")]
+        sep: String,
+
+        /// Delete the merged transcripts from history (their files are kept)
+        #[arg(long)]
+        delete_sources: bool,
+    },
+
+    /// Show a word-level diff between two transcripts
+    Diff {
+        /// Transcript ID (or prefix, or alias) of the original
+        old: String,
+
+        /// Transcript ID (or prefix, or alias) to compare against it
+        new: String,
+    },
+
+    /// Give a transcript a memorable alias usable in place of its ID
+    Rename {
+        /// Transcript ID (or prefix, or current alias)
+        id: String,
+
+        /// New alias, e.g. grocery-list
+        alias: String,
+    },
+
+    /// Attach a note to a transcript, e.g. a reminder of what to do with it
+    Note {
+        /// Transcript ID (or prefix, or alias)
+        id: String,
+
+        /// The note, replacing any earlier one
+        #[arg(required_unless_present = "clear", conflicts_with = "clear")]
+        text: Option<String>,
+
+        /// Remove the note
+        #[arg(long)]
+        clear: bool,
+    },
+
+    /// Keep a transcript at the top of history
+    Pin {
+        /// Transcript ID (or prefix, or alias)
+        id: String,
+    },
+
+    /// Return a pinned transcript to its place in history
+    Unpin {
+        /// Transcript ID (or prefix, or alias)
+        id: String,
+    },
+
+    /// Hide a transcript from history without deleting it
+    Archive {
+        /// Transcript ID (or prefix, or alias)
+        id: String,
+    },
+
+    /// Restore an archived transcript to history
+    Unarchive {
+        /// Transcript ID (or prefix, or alias)
+        id: String,
+    },
+
+    /// Delete the most recent transcript and its output file
+    Undo,
+
+    /// Delete old or orphaned tx-* files from the output directory
+    #[command(group = clap::ArgGroup::new("criteria").required(true).multiple(true))]
+    Clean {
+        /// Only files last modified before this: a date (2024-01-01), an
+        /// RFC 3339 time, an age like 12h or 2w, or a bare number of days
+        #[arg(long, value_name = "WHEN", value_parser = parse_cutoff, group = "criteria")]
+        older_than: Option<DateTime<Local>>,
+
+        /// Only files no transcript in history refers to
+        #[arg(long, group = "criteria")]
+        orphans: bool,
+
+        /// List what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Delete transcripts older than a cutoff, optionally exporting them first
+    ///
+    /// Pinned transcripts are always kept.
+    Prune {
+        /// Cutoff: a date (2024-01-01), an RFC 3339 time, or an age like 30d
+        #[arg(long, value_name = "WHEN", value_parser = parse_when)]
+        older_than: DateTime<Local>,
+
+        /// Write the pruned transcripts to this JSON file (as `tx export`)
+        /// before deleting them
+        #[arg(long, value_name = "PATH")]
+        archive: Option<PathBuf>,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Compare the speed and output of every local model on a WAV file
+    Bench {
+        /// Audio file to transcribe
+        file: PathBuf,
+    },
+
+    /// Load the model once to prime the OS file cache, then exit
+    Warmup {
+        /// Also run a short silent transcription
+        #[arg(long)]
+        decode: bool,
+    },
+
+    /// Check audio, model, clipboard and history setup, with fixes for
+    /// anything broken
+    Doctor,
+
+    /// List the language codes accepted by --lang
+    Languages,
+
+    /// Print the JSON Schema for --json, --format json and export output
+    JsonSchema,
+
+    /// Export all transcripts as JSON to stdout
+    Export {
+        /// Reduce working_dir and output_path to their final component
+        #[arg(long)]
+        redact_paths: bool,
+    },
+
+    /// Start recording in the background and return at once; `tx finish`
+    /// stops and transcribes it
+    Start,
+
+    /// Stop the background recording from `tx start` and transcribe it, as
+    /// the recording flags would for the mic
+    Finish,
+
+    /// Record until interrupted, saving the audio to WAV (run by `tx start`)
+    #[command(hide = true)]
+    Recorder { wav: PathBuf },
+
+    /// Keep the last few seconds of audio in memory; press Enter to
+    /// transcribe them
+    Listen {
+        /// How much recent audio to keep
+        #[arg(long, value_name = "SECS", default_value = "30", value_parser = parse_secs)]
+        seconds: Duration,
+    },
+
+    /// Manage Whisper models
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum ModelsCommand {
+    /// Download a model (e.g. small.en) into the models directory
+    Download {
+        /// Model name, such as tiny.en, base.en, small.en or medium.en
+        name: String,
 
-    match cli.command {
-        None => record(&cli),
-        Some(Command::History { limit }) => history(limit),
-        Some(Command::Show { id }) => show(&id),
-        Some(Command::Copy { id }) => copy(&id),
-        Some(Command::Last) => last(),
-    }
+        /// Print the URL, target path and expected size without downloading
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Download again even if the model is already present, replacing
+        /// it once the new file is complete
+        #[arg(long)]
+        force: bool,
+
+        /// Don't ask before replacing a large model
+        #[arg(short, long, requires = "force")]
+        yes: bool,
+    },
 }
 
-fn record(cli: &Cli) -> Result<()> {
-    // Get model path first (fails early with helpful message)
-    let model_path = model::get_model_path()?;
+/// Parses a non-negative number of seconds, allowing fractions.
+fn parse_secs(value: &str) -> Result<Duration, String> {
+    let secs: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid seconds '{}'", value))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("seconds must be >= 0, got '{}'", value))
+}
 
-    // Record audio
-    let samples = audio::record_until_enter(cli.quiet)?;
+/// Parses a positive, finite gain factor.
+fn parse_gain(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(gain) if gain.is_finite() && gain > 0.0 => Ok(gain),
+        _ => Err(format!("gain must be a positive number, got '{}'", value)),
+    }
+}
 
-    // Check for minimum audio
-    if samples.len() < (audio::SAMPLE_RATE / 2) as usize {
-        eprintln!("No audio recorded.");
-        process::exit(1);
+/// Parses a stop key: "space", "enter" (the default, line-based), or any
+/// single character.
+fn parse_key(value: &str) -> Result<Option<char>, String> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_control() => Ok(Some(c)),
+        _ if value.eq_ignore_ascii_case("space") => Ok(Some(' ')),
+        _ if value.eq_ignore_ascii_case("enter") => Ok(None),
+        _ => Err(format!(
+            "stop key must be \"space\", \"enter\" or a single character, got '{}'",
+            value
+        )),
     }
+}
 
-    // Show transcribing status in quiet mode
-    if cli.quiet {
-        eprint!("\x1b[90mTranscribing...\x1b[0m");
-        io::stderr().flush().ok();
+/// Parses a point in time: `YYYY-MM-DD` (local midnight), an RFC 3339
+/// timestamp, or an age before now such as `30m`, `12h`, `7d` or `2w`.
+fn parse_when(value: &str) -> Result<DateTime<Local>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|t| t.and_local_timezone(Local).earliest())
+            .ok_or_else(|| format!("'{}' is not a valid local date", value));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Local));
     }
 
-    // Transcribe
-    let text = transcribe::transcribe(&samples, &model_path, cli.quiet)?;
+    let invalid = || {
+        format!(
+            "invalid time '{}' (expected e.g. 2024-01-01, 2024-01-01T09:30:00Z or 7d)",
+            value
+        )
+    };
+    let (split, _) = value.char_indices().last().ok_or_else(invalid)?;
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let age = match unit {
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        "w" => chrono::Duration::try_weeks(amount),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+    Local::now().checked_sub_signed(age).ok_or_else(invalid)
+}
 
-    // Clear status line in quiet mode
-    if cli.quiet {
-        eprint!("\r\x1b[K");
-        io::stderr().flush().ok();
+/// Parses a cutoff as `parse_when` does, also taking a bare number as an
+/// age in days (what `tx clean --older-than` always accepted).
+fn parse_cutoff(value: &str) -> Result<DateTime<Local>, String> {
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        return parse_when(&format!("{}d", value));
     }
+    parse_when(value)
+}
 
-    if text.is_empty() {
-        eprintln!("Could not transcribe.");
-        process::exit(1);
+fn main() {
+    let cli = Cli::parse();
+    init_logging();
+    if let Some(dir) = &cli.data_dir {
+        config::set_data_dir(dir.clone());
     }
+    if let Err(e) = run(&cli) {
+        if cli.json {
+            output::print_json_error(&format!("{:#}", e));
+        } else {
+            eprintln!("Error: {:#}", e);
+        }
+        process::exit(error::code_of(&e) as i32);
+    }
+}
 
-    // Save to database
-    let conn = db::open()?;
-    let cwd = std::env::current_dir()
-        .map(|p| p.display().to_string())
-        .unwrap_or_else(|_| "unknown".to_string());
-    let id = db::save(&conn, &text, &cwd)?;
+/// Sends debug logs to stderr when `TX_LOG` (or failing that `RUST_LOG`)
+/// holds a filter such as `debug` or `tx=debug`. Unset, nothing is logged.
+fn init_logging() {
+    let Some(filter) = ["TX_LOG", "RUST_LOG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+    else {
+        return;
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::new(filter))
+        .with_writer(io::stderr)
+        .init();
+}
+
+/// Reports a failure and exits with `code`, as `{"error": ...}` under `--json`.
+fn fail(cli: &Cli, code: Code, message: &str) -> ! {
+    if cli.json {
+        output::print_json_error(message);
+    } else {
+        eprintln!("{}", message);
+    }
+    process::exit(code as i32);
+}
 
-    // Save to file
-    let out_path = output::save_to_file(&text, &cli.output_dir)?;
+fn run(cli: &Cli) -> Result<()> {
+    let config = config::Config::load()?;
 
-    // Copy to clipboard
-    if !cli.no_clip {
-        if let Err(e) = output::copy_to_clipboard(&text) {
-            if !cli.quiet {
-                eprintln!("\x1b[90m(Clipboard unavailable: {})\x1b[0m", e);
+    match &cli.command {
+        None => record(cli, &config),
+        Some(Command::History {
+            limit,
+            all,
+            since,
+            until,
+            dir,
+            here,
+            count,
+            preview_len,
+        }) => {
+            let dir = if *here {
+                Some(std::env::current_dir().context("Cannot determine current directory")?)
+            } else {
+                dir.clone()
+            };
+            let filter = db::Filter {
+                include_archived: *all,
+                since: *since,
+                until: *until,
+                dir: dir.as_deref().map(absolute_dir).transpose()?,
+                pinned_first: true,
+            };
+            if *count {
+                println!("{}", db::count(&open_db(&config)?, &filter)?);
+                Ok(())
+            } else {
+                history(&config, *limit, utc(cli, &config), &filter, *preview_len)
             }
         }
+        Some(Command::Dirs) => dirs(&config),
+        Some(Command::Show { id, play, raw }) => show(cli, &config, id, *play, *raw),
+        Some(Command::Play { id }) => play(cli, &config, id),
+        Some(Command::Copy { id, last, sep }) => match (id, last) {
+            (_, Some(n)) => copy_last(&config, *n as usize, sep),
+            (Some(id), None) => copy(cli, &config, id),
+            (None, None) => unreachable!("clap requires an id or --last"),
+        },
+        Some(Command::Last) => last(&config),
+        Some(Command::Merge {
+            ids,
+            sep,
+            delete_sources,
+        }) => merge(cli, &config, ids, sep, *delete_sources),
+        Some(Command::Diff { old, new }) => diff(&config, old, new),
+        Some(Command::Rename { id, alias }) => rename(&config, id, alias),
+        Some(Command::Note { id, text, .. }) => note(&config, id, text.as_deref()),
+        Some(Command::Pin { id }) => pin(&config, id, true),
+        Some(Command::Unpin { id }) => pin(&config, id, false),
+        Some(Command::Archive { id }) => archive(&config, id, true),
+        Some(Command::Unarchive { id }) => archive(&config, id, false),
+        Some(Command::Undo) => undo(&config),
+        Some(Command::Clean {
+            older_than,
+            orphans,
+            dry_run,
+        }) => clean(cli, &config, *older_than, *orphans, *dry_run),
+        Some(Command::Prune {
+            older_than,
+            archive,
+            yes,
+        }) => prune(&config, *older_than, archive.as_deref(), *yes),
+        Some(Command::Export { redact_paths }) => export(&config, *redact_paths, utc(cli, &config)),
+        Some(Command::Bench { file }) => bench(cli, file),
+        Some(Command::Warmup { decode }) => warmup(cli, *decode),
+        Some(Command::Doctor) => doctor(&config),
+        Some(Command::Languages) => languages(),
+        Some(Command::JsonSchema) => {
+            println!("{}", serde_json::to_string_pretty(&output::json_schema())?);
+            Ok(())
+        }
+        Some(Command::Start) => start(cli),
+        Some(Command::Finish) => finish(cli, &config),
+        Some(Command::Recorder { wav }) => recorder(cli, &config, wav),
+        Some(Command::Listen { seconds }) => listen(cli, &config, *seconds),
+        Some(Command::Models { command }) => match command {
+            ModelsCommand::Download {
+                name,
+                dry_run,
+                force,
+                yes,
+            } => download(cli, name, *dry_run, *force, *yes),
+        },
     }
+}
 
-    // Output
-    if cli.quiet {
-        println!("{}", text);
+fn record(cli: &Cli, config: &config::Config) -> Result<()> {
+    let clip = if cli.from_clip && !cli.dry_run {
+        Some(clipboard_path()?)
     } else {
-        eprintln!("\n\x1b[92mSaved:\x1b[0m {} \x1b[90m({})\x1b[0m", out_path.display(), id);
-        println!("\n---\n{}\n---\n", text);
-        if !cli.no_clip {
-            eprintln!("\x1b[90mCopied to clipboard.\x1b[0m");
-        }
+        None
+    };
+    let inputs: Vec<InputFile> = clip
+        .iter()
+        .chain(&cli.input)
+        .map(|path| InputFile {
+            path,
+            source: if path == std::path::Path::new("-") {
+                "stdin".to_string()
+            } else {
+                format!(
+                    "file:{}",
+                    std::path::absolute(path).unwrap_or(path.clone()).display()
+                )
+            },
+        })
+        .collect();
+    if inputs.len() > 1 && cli.output_file.is_some() {
+        anyhow::bail!("--output-file takes a single --input; use --output-dir for several");
     }
-
-    Ok(())
+    record_from(cli, config, &inputs)
 }
 
-fn history(limit: usize) -> Result<()> {
-    let conn = db::open()?;
-    let transcripts = db::list(&conn, limit)?;
+/// An audio file to transcribe, and the source stored with its transcripts.
+struct InputFile<'a> {
+    path: &'a std::path::Path,
+    source: String,
+}
 
-    if transcripts.is_empty() {
-        println!("No transcripts yet.");
-        return Ok(());
-    }
+/// Where a run over `--input` files stands: `--keep-going`, the
+/// `--report` rows, and how many files failed.
+struct Batch {
+    keep_going: bool,
+    report: Option<output::Report>,
+    failed: usize,
+}
 
-    for t in transcripts {
-        let preview: String = t.text.chars().take(60).collect();
-        let preview = if t.text.len() > 60 {
-            format!("{}...", preview)
-        } else {
-            preview
+impl Batch {
+    /// Records that `file` failed with `error`. Returns the error (ending the
+    /// run) unless `--keep-going` says to carry on with the next file.
+    fn failed(&mut self, file: Option<&InputFile>, error: anyhow::Error) -> Result<()> {
+        let Some(file) = file else {
+            return Err(error);
         };
-        let time = t.timestamp.format("%Y-%m-%d %H:%M");
-        println!(
-            "\x1b[93m{}\x1b[0m  \x1b[90m{}\x1b[0m  {}",
-            t.id, time, preview
+        self.failed += 1;
+        if let Some(report) = &mut self.report {
+            report.row(file.path, &[], Some(&error))?;
+        }
+        if !self.keep_going {
+            return Err(error);
+        }
+        eprintln!(
+            "\x1b[93mWarning:\x1b[0m {}: {:#}",
+            file.path.display(),
+            error
         );
+        Ok(())
     }
 
-    Ok(())
+    /// Records the transcripts `saved` from `file`.
+    fn done(&mut self, file: &InputFile, saved: &[(String, Option<PathBuf>)]) -> Result<()> {
+        match &mut self.report {
+            Some(report) => report.row(file.path, saved, None),
+            None => Ok(()),
+        }
+    }
 }
 
-fn show(id: &str) -> Result<()> {
-    let conn = db::open()?;
+/// Reads the path for `--from-clip` from the clipboard. File managers copy
+/// either a plain path or a `file://` URI; either works, trimmed of quotes.
+fn clipboard_path() -> Result<PathBuf> {
+    let text = output::clipboard_text()?;
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    let Some(line) = lines.next() else {
+        anyhow::bail!("The clipboard is empty; copy an audio file's path first");
+    };
+    if lines.next().is_some() {
+        anyhow::bail!("The clipboard holds more than one line; copy a single file's path");
+    }
+    let line = line.trim_matches(|c| c == '"' || c == '\'');
+    let path = match line.strip_prefix("file://") {
+        // Drop any host part; only local files can be read
+        Some(uri) => PathBuf::from(percent_decode(&uri[uri.find('/').unwrap_or(0)..])),
+        None => PathBuf::from(line),
+    };
+    if !path.is_file() {
+        anyhow::bail!(
+            "The clipboard doesn't hold the path of an existing file: {}",
+            path.display()
+        );
+    }
+    if !audio::is_wav(&path) {
+        anyhow::bail!(
+            "{} (from the clipboard) isn't a WAV file tx can read",
+            path.display()
+        );
+    }
+    Ok(path)
+}
 
-    match db::find_by_prefix(&conn, id)? {
-        Some(t) => {
-            println!("{}", t.text);
+/// Decodes `%XX` escapes in a URI path, leaving malformed ones as they are.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
         }
-        None => {
-            eprintln!("No transcript found with ID starting with '{}'", id);
-            process::exit(1);
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Records from the mic, or transcribes `inputs` in turn instead when
+/// there are any. With several, or `--keep-going` or `--report`, a file
+/// that fails is reported as such rather than exiting on the spot.
+fn record_from(cli: &Cli, config: &config::Config, inputs: &[InputFile]) -> Result<()> {
+    if cli.lang_per_segment && cli.lang != "auto" {
+        anyhow::bail!("--lang-per-segment needs --lang auto");
+    }
+    if cli.dry_run {
+        match model::find_model()? {
+            Some(path) => println!("Model already present: {}", path.display()),
+            None => model::Download::default_model()?.print_plan(cli.force_download),
         }
+        return Ok(());
     }
 
-    Ok(())
-}
+    // Flags that end recording and transcription early
+    let stop = Arc::new(AtomicBool::new(false));
+    let cancel = Arc::new(AtomicBool::new(false));
+    let timed_out = Arc::new(AtomicBool::new(false));
+    if let Some(limit) = cli.timeout {
+        start_watchdog(
+            limit,
+            vec![Arc::clone(&stop), Arc::clone(&cancel)],
+            Arc::clone(&timed_out),
+            cli.json,
+        );
+    }
+    let check_timeout = || {
+        if timed_out.load(Ordering::Relaxed) {
+            fail(
+                cli,
+                Code::General,
+                &timeout_message(cli.timeout.unwrap_or_default()),
+            );
+        }
+    };
+
+    // Get model path first (fails early with helpful message)
+    let model_path = model_path(cli)?;
 
-fn copy(id: &str) -> Result<()> {
-    let conn = db::open()?;
+    let mut sinks = checked_sinks(cli, config)?;
+    let id_length = id_length(cli, config);
 
-    match db::find_by_prefix(&conn, id)? {
-        Some(t) => {
-            output::copy_to_clipboard(&t.text)?;
-            eprintln!("Copied to clipboard.");
+    let cwd = std::env::current_dir()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    // Ctrl-C during transcription keeps whatever has been decoded so far; in
+    // --loop mode it also ends the current recording and the loop
+    let install_handler = || {
+        let flags = [Arc::clone(&cancel), Arc::clone(&stop)];
+        ctrlc::set_handler(move || {
+            if flags[0].swap(true, Ordering::Relaxed) {
+                process::exit(130);
+            }
+            flags[1].store(true, Ordering::Relaxed);
+        })
+        .context("Failed to install Ctrl-C handler")
+    };
+    if cli.repeat || inputs.len() > 1 {
+        install_handler()?;
+    }
+    let batch_mode = inputs.len() > 1 || cli.keep_going || cli.report.is_some();
+    let mut batch = Batch {
+        keep_going: cli.keep_going,
+        report: cli
+            .report
+            .as_deref()
+            .map(output::Report::create)
+            .transpose()?,
+        failed: 0,
+    };
+
+    let opts = transcribe::Options {
+        cancel: Some(Arc::clone(&cancel)),
+        language: transcribe::language_option(&cli.lang),
+        word_timestamps: cli.save_audio || cli.show_confidence || cli.debug_segments,
+        fallback_language: fallback_lang(config)?,
+        verbose: cli.verbose,
+        ..Default::default()
+    };
+
+    let stop_key = stop_key(cli, config)?;
+
+    // Loaded after the first recording, then reused by every later one
+    let mut ctx = None;
+    let mut transcribed = 0;
+    let mut files = inputs.iter();
+    'files: loop {
+        let file = match files.next() {
+            None if !inputs.is_empty() => break,
+            file => file,
+        };
+        if cli.repeat && transcribed > 0 && !cli.quiet {
+            eprintln!("\x1b[90m(Next recording; Ctrl-C or an empty recording to finish)\x1b[0m");
         }
-        None => {
-            eprintln!("No transcript found with ID starting with '{}'", id);
-            process::exit(1);
+        if let Some(file) = file
+            && inputs.len() > 1
+            && !cli.quiet
+        {
+            eprintln!(
+                "\x1b[90m({}/{}) {}\x1b[0m",
+                inputs.len() - files.len(),
+                inputs.len(),
+                file.path.display()
+            );
         }
-    }
 
-    Ok(())
-}
+        // Record audio, or read it from --input
+        let samples = match file {
+            Some(file) => match read_input(cli, file.path) {
+                Ok(samples) => samples,
+                Err(e) => {
+                    batch.failed(Some(file), e)?;
+                    continue;
+                }
+            },
+            None => {
+                // Listening for the stop phrase or showing live text needs the
+                // model during recording
+                let watching = cli.stop_phrase.is_some() || cli.live;
+                if watching && ctx.is_none() {
+                    ctx = Some(transcribe::load_model(&model_path, cli.quiet)?);
+                }
+                let check_opts = transcribe::Options {
+                    language: opts.language.clone(),
+                    fallback_language: opts.fallback_language.clone(),
+                    ..Default::default()
+                };
+                let mut check = |tail: &[f32]| {
+                    let Some(ctx) = &ctx else {
+                        return false;
+                    };
+                    let Ok(t) = transcribe::transcribe(ctx, tail, &check_opts) else {
+                        return false;
+                    };
+                    if cli.live {
+                        show_live(&t.text);
+                    }
+                    cli.stop_phrase.as_ref().is_some_and(|phrase| {
+                        text::strip_trailing_phrase(&t.text, phrase).is_some()
+                    })
+                };
+                if let Some(phrase) = &cli.stop_phrase
+                    && !cli.quiet
+                {
+                    eprintln!("\x1b[90m(Say \"{}\" to stop)\x1b[0m", phrase);
+                }
+                let (window, every, after) = if cli.live {
+                    (LIVE_WINDOW, LIVE_EVERY, LIVE_EVERY)
+                } else {
+                    (STOP_PHRASE_WINDOW, STOP_PHRASE_EVERY, STOP_PHRASE_WINDOW)
+                };
+                let samples = audio::record_until_enter(
+                    &audio::Options {
+                        quiet: cli.quiet,
+                        delay: cli.delay,
+                        stop: Some(Arc::clone(&stop)),
+                        gain: cli.input_gain,
+                        beep: cli.beep,
+                        sources: sources(cli),
+                        stop_key,
+                        retries: config.device_retries,
+                        verbose: cli.verbose,
+                        output_device: cli.output_device.clone(),
+                        ..Default::default()
+                    },
+                    watching.then_some(audio::Watch {
+                        window,
+                        every,
+                        after,
+                        check: &mut check,
+                    }),
+                )?;
+                if cli.live {
+                    eprint!("\r\x1b[K");
+                }
+                samples
+            }
+        };
+        check_timeout();
 
-fn last() -> Result<()> {
-    let conn = db::open()?;
-    let transcripts = db::list(&conn, 1)?;
+        if cli.repeat && cancel.load(Ordering::Relaxed) {
+            break;
+        }
 
-    match transcripts.into_iter().next() {
-        Some(t) => {
-            println!("{}", t.text);
+        // Check for minimum audio; capture and --input both deliver 16kHz
+        if audio::too_short(&samples, audio::SAMPLE_RATE) {
+            if cli.repeat {
+                break;
+            }
+            if batch_mode {
+                batch.failed(file, error::tagged(Code::NoAudio, "No audio recorded."))?;
+                continue;
+            }
+            fail(cli, Code::NoAudio, "No audio recorded.");
         }
-        None => {
-            eprintln!("No transcripts yet.");
-            process::exit(1);
+
+        if !cli.repeat && inputs.len() <= 1 {
+            install_handler()?;
         }
-    }
 
-    Ok(())
+        let chunks = match cli.split_on_silence {
+            Some(gap) => audio::split_on_silence(&samples, gap, MIN_SPLIT_SECS),
+            None => vec![&samples[..]],
+        };
+        let numbered = chunks.len() > 1;
+
+        let mut parts = 0;
+        let mut saved = Vec::new();
+        for chunk in chunks {
+            if cancel.load(Ordering::Relaxed) {
+                break;
+            }
+
+            // Show transcribing status in quiet mode
+            if cli.quiet {
+                eprint!("\x1b[90mTranscribing...\x1b[0m");
+                io::stderr().flush().ok();
+            }
+
+            // Transcribe. A lone decode loads the model just for itself;
+            // anything that decodes again keeps it loaded for reuse
+            let single_shot = ctx.is_none()
+                && !cli.repeat
+                && !numbered
+                && !cli.lang_per_segment
+                && !cli.retry_empty
+                && inputs.len() <= 1;
+            let decoded = if single_shot {
+                transcribe::transcribe_file(&model_path, chunk, &opts, cli.quiet)
+            } else {
+                if ctx.is_none() {
+                    ctx = Some(transcribe::load_model(&model_path, cli.quiet)?);
+                }
+                let ctx = ctx.as_ref().expect("model loaded above");
+                let pieces = cli
+                    .lang_per_segment
+                    .then(|| audio::split_ranges(chunk, LANG_PIECE_GAP, LANG_PIECE_SECS));
+                let decode = |opts: &transcribe::Options| match &pieces {
+                    Some(pieces) => transcribe::transcribe_pieces(ctx, chunk, pieces, opts),
+                    None => transcribe::transcribe(ctx, chunk, opts),
+                };
+                decode(&opts).and_then(|transcription| {
+                    if !cli.retry_empty
+                        || !transcription.text.is_empty()
+                        || cancel.load(Ordering::Relaxed)
+                    {
+                        return Ok(transcription);
+                    }
+                    if cli.verbose {
+                        eprintln!("\x1b[90m(No text; retrying with relaxed decoding)\x1b[0m");
+                    }
+                    let relaxed = transcribe::Options {
+                        relaxed: true,
+                        ..opts.clone()
+                    };
+                    decode(&relaxed)
+                })
+            };
+            let mut transcription = match decoded {
+                Ok(transcription) => transcription,
+                Err(e) => {
+                    batch.failed(file, e)?;
+                    continue 'files;
+                }
+            };
+
+            // Clear status line in quiet mode
+            if cli.quiet {
+                eprint!("\r\x1b[K");
+                io::stderr().flush().ok();
+            }
+
+            check_timeout();
+
+            if cancel.load(Ordering::Relaxed) {
+                eprintln!("\x1b[93mTranscription cancelled; keeping partial text.\x1b[0m");
+            }
+
+            warn_partial(&transcription);
+            warn_lossy(cli, &transcription);
+            debug_segments(cli, &transcription)?;
+            if let Some(phrase) = &cli.stop_phrase
+                && let Some(text) = text::strip_trailing_phrase(&transcription.text, phrase)
+            {
+                transcription.text = text;
+                // Kept in step, or --label-turns would rebuild the phrase
+                text::strip_trailing_phrase_segments(&mut transcription.segments, phrase);
+            }
+            if transcription.text.is_empty() {
+                continue;
+            }
+
+            // What Whisper said, before the post-processing below
+            let raw_text = transcription.text.clone();
+            if let Some(gap) = cli.label_turns {
+                let silences: Vec<_> = audio::find_silences(chunk, gap)
+                    .into_iter()
+                    .map(|r| samples_to_ms(r.start)..samples_to_ms(r.end))
+                    .collect();
+                transcription.text = text::label_turns(&transcription.segments, &silences);
+            }
+
+            if cli.strip_filler {
+                transcription.text = text::strip_filler(&transcription.text, &fillers(config));
+            }
+            if cli.tidy {
+                transcription.text = text::tidy(&transcription.text);
+            }
+
+            parts += 1;
+            transcribed += 1;
+            let mut delivery = output::Delivery::new(
+                transcription,
+                chunk.to_vec(),
+                cwd.clone(),
+                id_length,
+                utc(cli, config),
+            );
+            delivery.model = Some(model::model_name(&model_path));
+            delivery.source = Some(file.map_or("mic", |f| f.source.as_str()).to_string());
+            delivery.raw_text = Some(raw_text);
+            if numbered {
+                delivery.part = Some(parts);
+            }
+            shorten(cli, &mut delivery);
+            summarize(cli, &mut delivery);
+            if let Err(e) = deliver(&mut sinks, &mut delivery) {
+                batch.failed(file, e)?;
+                continue 'files;
+            }
+            saved.push((delivery.id.clone(), delivery.file.clone()));
+        }
+        if parts > 0 {
+            alert(cli);
+        }
+
+        if let Some(file) = file {
+            if batch_mode && saved.is_empty() && !cli.allow_empty {
+                let error = error::tagged(Code::Empty, "Could not transcribe.");
+                batch.failed(Some(file), error)?;
+            } else {
+                batch.done(file, &saved)?;
+            }
+        }
+
+        if cancel.load(Ordering::Relaxed) || (!cli.repeat && files.len() == 0) {
+            break;
+        }
+    }
+
+    if batch.failed > 0 {
+        anyhow::bail!("{} of {} input files failed", batch.failed, inputs.len());
+    }
+    if transcribed == 0 && !cli.repeat && !batch_mode {
+        if !cli.allow_empty {
+            fail(cli, Code::Empty, "Could not transcribe.");
+        }
+        if cli.json {
+            let empty = output::JsonTranscript {
+                id: None,
+                text: "",
+                timestamp: if utc(cli, config) {
+                    Utc::now().to_rfc3339()
+                } else {
+                    Local::now().to_rfc3339()
+                },
+                working_dir: &cwd,
+                file: None,
+                segments: &[],
+                language: None,
+            };
+            println!("{}", serde_json::to_string(&empty)?);
+        } else if cli.quiet && !cli.no_newline {
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
+/// How long `tx start` watches the new recorder for an early failure.
+const RECORDER_STARTUP: Duration = Duration::from_millis(500);
+
+/// How long `tx finish` waits for the recorder to save its audio.
+const RECORDER_SHUTDOWN: Duration = Duration::from_secs(10);
+
+/// Where the background recorder from `tx start` keeps its state: its
+/// `pid`, the `wav` it saves and a `log` of its stderr.
+fn background_file(ext: &str) -> Result<PathBuf> {
+    Ok(config::data_dir()?.join(format!("background.{}", ext)))
+}
+
+/// The background recorder's pid, if one was started and is still running.
+/// A pid file left behind by a recorder that died is removed.
+fn running_recorder() -> Result<Option<i32>> {
+    let pid_file = background_file("pid")?;
+    let pid = match std::fs::read_to_string(&pid_file) {
+        Ok(pid) => pid.trim().parse().ok(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Cannot read {}", pid_file.display())),
+    };
+    match pid.filter(|&pid| process_alive(pid)) {
+        Some(pid) => Ok(Some(pid)),
+        None => {
+            std::fs::remove_file(&pid_file).ok();
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn process_alive(pid: i32) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: i32) -> bool {
+    false
+}
+
+/// The end of the recorder's log, for explaining why it stopped.
+fn recorder_log() -> String {
+    background_file("log")
+        .ok()
+        .and_then(|log| std::fs::read_to_string(log).ok())
+        .map(|log| log.trim().to_string())
+        .filter(|log| !log.is_empty())
+        .unwrap_or_else(|| "no output".to_string())
+}
+
+#[cfg(unix)]
+fn start(cli: &Cli) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    if let Some(pid) = running_recorder()? {
+        anyhow::bail!(
+            "Already recording in the background (pid {}); run `tx finish` first",
+            pid
+        );
+    }
+    let wav = background_file("wav")?;
+    let _ = std::fs::remove_file(&wav);
+
+    let mut command =
+        process::Command::new(std::env::current_exe().context("Cannot find the tx executable")?);
+    for device in &cli.device {
+        command.arg("--device").arg(device);
+    }
+    if cli.loopback {
+        command.arg("--loopback");
+    }
+    if let Some(gain) = cli.input_gain {
+        command.arg("--input-gain").arg(gain.to_string());
+    }
+    let log = std::fs::File::create(background_file("log")?)?;
+    // Its own process group, so Ctrl-C in this terminal doesn't reach it
+    let mut child = command
+        .arg("recorder")
+        .arg(&wav)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(log)
+        .process_group(0)
+        .spawn()
+        .context("Failed to start background recorder")?;
+
+    std::thread::sleep(RECORDER_STARTUP);
+    if child.try_wait()?.is_some() {
+        anyhow::bail!("Background recorder failed: {}", recorder_log());
+    }
+    std::fs::write(background_file("pid")?, child.id().to_string())?;
+
+    if !cli.quiet {
+        eprintln!(
+            "\x1b[93m[Recording in the background]\x1b[0m Run `tx finish` to transcribe (pid {}).",
+            child.id()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn start(_cli: &Cli) -> Result<()> {
+    anyhow::bail!("tx start is only supported on Unix")
+}
+
+fn finish(cli: &Cli, config: &config::Config) -> Result<()> {
+    let wav = background_file("wav")?;
+    match running_recorder()? {
+        Some(pid) => stop_recorder(pid)?,
+        None if wav.exists() => {
+            // The recorder already stopped on its own, but saved its audio
+        }
+        None => {
+            eprintln!("No background recording; start one with `tx start`.");
+            process::exit(Code::NoAudio as i32);
+        }
+    }
+    std::fs::remove_file(background_file("pid")?).ok();
+
+    if !wav.exists() {
+        anyhow::bail!(
+            "Background recorder stopped without saving audio: {}",
+            recorder_log()
+        );
+    }
+    // Tagged as mic: the WAV is only how the recording got here
+    let input = InputFile {
+        path: &wav,
+        source: "mic".to_string(),
+    };
+    record_from(cli, config, &[input])?;
+    std::fs::remove_file(&wav).ok();
+    Ok(())
+}
+
+/// Interrupts the recorder and waits for it to save its audio and exit.
+#[cfg(unix)]
+fn stop_recorder(pid: i32) -> Result<()> {
+    unsafe {
+        libc::kill(pid, libc::SIGINT);
+    }
+    let start = Instant::now();
+    while process_alive(pid) {
+        if start.elapsed() > RECORDER_SHUTDOWN {
+            anyhow::bail!("Background recorder (pid {}) didn't stop", pid);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn stop_recorder(_pid: i32) -> Result<()> {
+    anyhow::bail!("tx finish is only supported on Unix")
+}
+
+/// The process behind `tx start`: records until interrupted, then saves
+/// the audio for `tx finish`.
+fn recorder(cli: &Cli, config: &config::Config, wav: &std::path::Path) -> Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&stop);
+    ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed))
+        .context("Failed to install Ctrl-C handler")?;
+
+    let samples = audio::record_until_enter(
+        &audio::Options {
+            quiet: true,
+            stop: Some(stop),
+            gain: cli.input_gain,
+            sources: sources(cli),
+            detached: true,
+            retries: config.device_retries,
+            ..Default::default()
+        },
+        None,
+    )?;
+    // Written beside the target and renamed, so `tx finish` never reads a
+    // half-written file
+    let partial = wav.with_extension("wav.partial");
+    audio::write_wav(&partial, &samples, audio::WavDepth::Float32)?;
+    std::fs::rename(&partial, wav).context("Failed to save background recording")?;
+    Ok(())
+}
+
+fn listen(cli: &Cli, config: &config::Config, seconds: Duration) -> Result<()> {
+    // Enter is what triggers a transcription here, so it can't be swapped
+    if cli.stop_key.is_some() {
+        anyhow::bail!("--stop-key doesn't apply to tx listen, which transcribes on each Enter");
+    }
+    // Every trigger is its own transcript, and they can't all have one path
+    if cli.output_file.is_some() {
+        anyhow::bail!(
+            "--output-file doesn't apply to tx listen, which saves a transcript per Enter"
+        );
+    }
+    let model_path = model_path(cli)?;
+    let mut sinks = checked_sinks(cli, config)?;
+    let id_length = id_length(cli, config);
+    let cwd = std::env::current_dir()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    // Load up front so a trigger only pays for decoding
+    let ctx = transcribe::load_model(&model_path, cli.quiet)?;
+    let opts = transcribe::Options {
+        language: transcribe::language_option(&cli.lang),
+        word_timestamps: cli.save_audio || cli.show_confidence || cli.debug_segments,
+        fallback_language: fallback_lang(config)?,
+        verbose: cli.verbose,
+        ..Default::default()
+    };
+
+    let audio_opts = audio::Options {
+        quiet: cli.quiet,
+        delay: cli.delay,
+        gain: cli.input_gain,
+        beep: cli.beep,
+        sources: sources(cli),
+        retries: config.device_retries,
+        verbose: cli.verbose,
+        output_device: cli.output_device.clone(),
+        ..Default::default()
+    };
+    audio::listen(seconds, &audio_opts, |samples| {
+        if audio::too_short(&samples, audio::SAMPLE_RATE) {
+            eprintln!("\x1b[90m(Nothing buffered yet)\x1b[0m");
+            return Ok(());
+        }
+
+        let mut transcription = transcribe::transcribe(&ctx, &samples, &opts)?;
+        warn_partial(&transcription);
+        warn_lossy(cli, &transcription);
+        debug_segments(cli, &transcription)?;
+        if transcription.text.is_empty() {
+            eprintln!(
+                "\x1b[90m(No speech in the last {:.0}s)\x1b[0m",
+                seconds.as_secs_f64()
+            );
+            return Ok(());
+        }
+        let raw_text = transcription.text.clone();
+        if cli.strip_filler {
+            transcription.text = text::strip_filler(&transcription.text, &fillers(config));
+        }
+        if cli.tidy {
+            transcription.text = text::tidy(&transcription.text);
+        }
+
+        let mut delivery = output::Delivery::new(
+            transcription,
+            samples,
+            cwd.clone(),
+            id_length,
+            utc(cli, config),
+        );
+        delivery.model = Some(model::model_name(&model_path));
+        delivery.source = Some("mic".to_string());
+        delivery.raw_text = Some(raw_text);
+        shorten(cli, &mut delivery);
+        summarize(cli, &mut delivery);
+        deliver(&mut sinks, &mut delivery)
+    })
+}
+
+/// Reads `--input`: a WAV file (at its header's rate), or raw f32 samples
+/// from stdin at `--input-rate` when the path is `-`.
+fn read_input(cli: &Cli, input: &std::path::Path) -> Result<Vec<f32>> {
+    let allow_resample = !cli.no_resample;
+    if input == std::path::Path::new("-") {
+        let rate = cli.input_rate.unwrap_or(audio::SAMPLE_RATE);
+        return audio::read_raw(io::stdin().lock(), rate, allow_resample);
+    }
+    if cli.input_rate.is_some() {
+        anyhow::bail!(
+            "--input-rate only applies to raw stdin input (--input -); WAV files use their header's rate"
+        );
+    }
+    audio::read_wav(input, allow_resample)
+}
+
+/// Applies `--first-sentence` and `--max-words`, keeping the full text for
+/// the history database.
+fn shorten(cli: &Cli, delivery: &mut output::Delivery) {
+    let mut short = delivery.text.as_str();
+    if cli.first_sentence {
+        short = text::first_sentence(short);
+    }
+    if let Some(n) = cli.max_words {
+        short = text::first_words(short, n);
+    }
+    if short.len() < delivery.text.len() {
+        let short = short.to_string();
+        delivery.full_text = Some(std::mem::replace(&mut delivery.text, short));
+    }
+}
+
+/// Sets the `--summary` of the delivery from its full, unshortened text.
+fn summarize(cli: &Cli, delivery: &mut output::Delivery) {
+    if cli.summary {
+        delivery.summary = text::summarize(delivery.full_text.as_ref().unwrap_or(&delivery.text));
+    }
+}
+
+/// Warns when a decoding error left only part of the audio transcribed.
+fn warn_partial(transcription: &transcribe::Transcription) {
+    if let Some(error) = &transcription.error {
+        eprintln!(
+            "\x1b[93mTranscription failed partway ({}); keeping the text decoded so far.\x1b[0m",
+            error
+        );
+    }
+}
+
+/// Gives each `--alert` signal, warning about any that can't be given; the
+/// transcript has been delivered by then, so none of them is fatal.
+fn alert(cli: &Cli) {
+    for &kind in &cli.alert {
+        if let Err(e) = output::alert(kind) {
+            eprintln!("\x1b[93mWarning:\x1b[0m Alert failed: {:#}", e);
+        }
+    }
+}
+
+/// In verbose mode, warns that some of the model's output wasn't valid UTF-8.
+fn warn_lossy(cli: &Cli, transcription: &transcribe::Transcription) {
+    if cli.verbose && transcription.lossy_segments > 0 {
+        eprintln!(
+            "\x1b[93mWarning:\x1b[0m {} segment(s) contained invalid UTF-8; some characters were replaced with U+FFFD",
+            transcription.lossy_segments
+        );
+    }
+}
+
+/// With `--debug-segments`, prints the transcription's segments as JSON on
+/// stderr, before any post-processing.
+fn debug_segments(cli: &Cli, transcription: &transcribe::Transcription) -> Result<()> {
+    if cli.debug_segments {
+        eprintln!("{}", serde_json::to_string(&transcription.segments)?);
+    }
+    Ok(())
+}
+
+/// Extra time the watchdog allows for a clean stop before forcing an exit.
+const WATCHDOG_GRACE: Duration = Duration::from_secs(2);
+
+/// After `limit`, sets `timed_out` and every flag in `stop`. If the process
+/// is still running `WATCHDOG_GRACE` later (e.g. stuck loading a model),
+/// exits with the timeout error directly.
+fn start_watchdog(
+    limit: Duration,
+    stop: Vec<Arc<AtomicBool>>,
+    timed_out: Arc<AtomicBool>,
+    json: bool,
+) {
+    std::thread::spawn(move || {
+        std::thread::sleep(limit);
+        timed_out.store(true, Ordering::Relaxed);
+        for flag in &stop {
+            flag.store(true, Ordering::Relaxed);
+        }
+
+        std::thread::sleep(WATCHDOG_GRACE);
+        let message = timeout_message(limit);
+        if json {
+            output::print_json_error(&message);
+        } else {
+            eprintln!("{}", message);
+        }
+        process::exit(Code::General as i32);
+    });
+}
+
+fn timeout_message(limit: Duration) -> String {
+    format!("Timed out after {:.1}s.", limit.as_secs_f64())
+}
+
+/// Converts a 16kHz sample offset to milliseconds.
+fn samples_to_ms(samples: usize) -> i64 {
+    (samples as u64 * 1000 / audio::SAMPLE_RATE as u64) as i64
+}
+
+/// Runs a transcript through every sink.
+///
+/// An optional sink that fails is reported once and dropped, so later
+/// transcripts in the same run skip it.
+fn deliver(sinks: &mut Vec<output::Sink>, delivery: &mut output::Delivery) -> Result<()> {
+    let mut failed = Vec::new();
+    for (i, sink) in sinks.iter().enumerate() {
+        if let Err(e) = sink.deliver(delivery) {
+            if !sink.is_optional() {
+                return Err(e);
+            }
+            eprintln!(
+                "\x1b[93m{} failed:\x1b[0m {:#} (skipping it for this run)",
+                sink.name(),
+                e
+            );
+            failed.push(i);
+        }
+    }
+
+    for i in failed.into_iter().rev() {
+        sinks.remove(i);
+    }
+
+    Ok(())
+}
+
+/// Builds the sinks and checks them before anything is recorded: a bad output
+/// directory is an error, an unusable clipboard is skipped with a note.
+fn checked_sinks(cli: &Cli, config: &config::Config) -> Result<Vec<output::Sink>> {
+    let mut sinks = sinks(cli, config)?;
+
+    // Fail on a bad output directory now, not after the user has spoken
+    for dir in sinks.iter().filter_map(output::Sink::output_dir) {
+        output::check_output_dir(dir)?;
+    }
+    if let Some(path) = &cli.output_file
+        && !cli.overwrite
+        && path.exists()
+    {
+        anyhow::bail!(
+            "{} already exists (pass --overwrite to replace it)",
+            path.display()
+        );
+    }
+
+    // Check the clipboard once up front rather than failing per transcript
+    if sinks.iter().any(output::Sink::is_clipboard)
+        && let Err(e) = output::check_clipboard()
+    {
+        eprintln!(
+            "\x1b[93mClipboard unavailable:\x1b[0m {:#} (transcripts won't be copied; pass --no-clip to hide this)",
+            e
+        );
+        sinks.retain(|s| !s.is_clipboard());
+    }
+    if sinks
+        .iter()
+        .any(|s| matches!(s, output::Sink::Osc52 { .. }))
+        && let Err(e) = output::check_osc52()
+    {
+        eprintln!(
+            "\x1b[93mWarning:\x1b[0m --osc52 needs a terminal ({:#}); not copying through it",
+            e
+        );
+        sinks.retain(|s| !matches!(s, output::Sink::Osc52 { .. }));
+    }
+
+    Ok(sinks)
+}
+
+/// Whether times are stored and shown in UTC: `--utc`, else the config.
+fn utc(cli: &Cli, config: &config::Config) -> bool {
+    cli.utc || config.utc.unwrap_or(false)
+}
+
+/// Formats a stored time for listings, in UTC (marked `Z`) or local time.
+fn display_time(timestamp: &DateTime<FixedOffset>, utc: bool) -> String {
+    if utc {
+        timestamp
+            .with_timezone(&Utc)
+            .format("%Y-%m-%d %H:%MZ")
+            .to_string()
+    } else {
+        timestamp
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    }
+}
+
+/// The `--stop-key`, else the config's `stop_key`; `None` means Enter.
+fn stop_key(cli: &Cli, config: &config::Config) -> Result<Option<char>> {
+    match cli.stop_key.as_deref().or(config.stop_key.as_deref()) {
+        Some(key) => parse_key(key).map_err(anyhow::Error::msg),
+        None => Ok(None),
+    }
+}
+
+/// Rewrites the `--live` line on stderr with the end of the tentative text,
+/// as much as fits the terminal.
+fn show_live(text: &str) {
+    let width = crossterm::terminal::size().map_or(80, |(columns, _)| columns as usize);
+    let text = text.trim();
+    let len = text.chars().count();
+    let shown: String = if len < width {
+        text.to_string()
+    } else {
+        let tail: String = text.chars().skip(len + 2 - width).collect();
+        format!("\u{2026}{}", tail)
+    };
+    eprint!("\r\x1b[K\x1b[90m{}\x1b[0m", shown);
+    io::stderr().flush().ok();
+}
+
+/// Words `--strip-filler` removes: the config's list, or the defaults.
+fn fillers(config: &config::Config) -> Vec<String> {
+    match &config.filler_words {
+        Some(words) => words.clone(),
+        None => text::DEFAULT_FILLERS
+            .iter()
+            .map(|w| w.to_string())
+            .collect(),
+    }
+}
+
+/// The input devices chosen by `--device` or `--loopback`; empty for the
+/// default input.
+fn sources(cli: &Cli) -> Vec<audio::Source> {
+    if cli.loopback {
+        return vec![audio::Source::Loopback];
+    }
+    cli.device
+        .iter()
+        .map(|name| audio::Source::Named(name.clone()))
+        .collect()
+}
+
+/// Length of new transcript IDs: flag, then config, then the default.
+fn id_length(cli: &Cli, config: &config::Config) -> usize {
+    cli.id_length
+        .map(usize::from)
+        .or(config.id_length)
+        .unwrap_or(db::DEFAULT_ID_LENGTH)
+}
+
+/// The configured `fallback_lang` for `--lang auto`, checked up front so a
+/// typo doesn't surface only when a short clip needs it.
+fn fallback_lang(config: &config::Config) -> Result<Option<String>> {
+    config
+        .fallback_lang
+        .as_deref()
+        .map(|lang| match transcribe::parse_language(lang) {
+            Ok(code) if code != "auto" && code != transcribe::NO_LANGUAGE => Ok(code),
+            Ok(code) => anyhow::bail!("fallback_lang must be a language code, not \"{}\"", code),
+            Err(e) => anyhow::bail!("Invalid fallback_lang in config: {}", e),
+        })
+        .transpose()
+}
+
+/// Opens the history database with the config file's settings.
+fn open_db(config: &config::Config) -> Result<rusqlite::Connection> {
+    db::open(&db::Settings::from(config))
+}
+
+/// Builds the output sinks for a recording, in delivery order.
+fn sinks(cli: &Cli, config: &config::Config) -> Result<Vec<output::Sink>> {
+    let output_dir = std::path::absolute(&cli.output_dir).context("Invalid output directory")?;
+    let file_opts = output::FileOptions {
+        format: cli.format,
+        crlf: cli.crlf || cfg!(windows),
+        bom: cli.bom,
+    };
+    let save_file = if cli.no_file || cli.save_file {
+        cli.save_file
+    } else {
+        config.save_file.unwrap_or(true)
+    };
+
+    // Resolve --append-to before recording so a bad ID fails fast
+    let store = db::Settings::from(config);
+    let append_to = match &cli.append_to {
+        Some(target) => {
+            let conn = db::open(&store)?;
+            let t = db::find_by_prefix(&conn, target)?.with_context(|| {
+                format!("No transcript found with ID starting with '{}'", target)
+            })?;
+            Some(output::Sink::AppendTo {
+                id: t.id,
+                file: t.output_path.map(PathBuf::from),
+                store,
+            })
+        }
+        None => None,
+    };
+
+    let mut sinks = Vec::new();
+    if let Some(path) = &cli.output_file {
+        sinks.push(output::Sink::ExactFile {
+            path: std::path::absolute(path).context("Invalid output file")?,
+            opts: file_opts,
+            overwrite: cli.overwrite,
+        });
+    } else if save_file && append_to.is_none() {
+        sinks.push(output::Sink::File(output_dir.clone(), file_opts));
+    }
+    if cli.save_audio {
+        sinks.push(output::Sink::Audio(output_dir.clone(), cli.wav_bitdepth));
+    }
+    sinks.push(append_to.unwrap_or(output::Sink::Db(store)));
+    if cli.copy_id {
+        sinks.push(output::Sink::ClipboardId);
+    } else if !cli.no_clip {
+        sinks.push(output::Sink::Clipboard { quote: cli.quote });
+    }
+    if cli.osc52 {
+        sinks.push(output::Sink::Osc52 { quote: cli.quote });
+    }
+    if let Some(path) = &cli.append_log {
+        sinks.push(output::Sink::AppendLog(path.clone()));
+    }
+    if let Some(command) = &cli.exec {
+        sinks.push(output::Sink::Exec(command.clone()));
+    }
+    if let Some(path) = &cli.fifo {
+        sinks.push(output::Sink::Fifo(path.clone()));
+    }
+    if cli.notify {
+        sinks.push(output::Sink::Notify);
+    }
+    if cli.json {
+        sinks.push(output::Sink::Json);
+    } else {
+        sinks.push(output::Sink::Stdout {
+            quiet: cli.quiet,
+            newline: !cli.no_newline,
+            layout: cli.display,
+            confidence: cli.show_confidence,
+            max_chars: cli.display_max,
+            merge_gap_ms: cli.max_segment_gap.map(|gap| gap.as_millis() as i64),
+        });
+    }
+    Ok(sinks)
+}
+
+/// History preview length when it isn't set and stdout isn't a terminal.
+const DEFAULT_PREVIEW_LEN: usize = 60;
+
+/// Shortest preview fitted to the terminal, however narrow it is.
+const MIN_PREVIEW_LEN: usize = 20;
+
+fn history(
+    config: &config::Config,
+    limit: usize,
+    utc: bool,
+    filter: &db::Filter,
+    preview_len: Option<usize>,
+) -> Result<()> {
+    let conn = open_db(config)?;
+    let transcripts = db::list(&conn, limit, filter)?;
+
+    if transcripts.is_empty() {
+        if filter.dir.is_some() {
+            println!("No transcripts from that directory.");
+        } else if filter.since.is_some() || filter.until.is_some() {
+            println!("No transcripts in that time range.");
+        } else {
+            println!("No transcripts yet.");
+        }
+        return Ok(());
+    }
+
+    let width = io::stdout()
+        .is_terminal()
+        .then(crossterm::terminal::size)
+        .and_then(Result::ok)
+        .map(|(columns, _)| columns as usize);
+
+    for t in transcripts {
+        let time = display_time(&t.timestamp, utc);
+        let archived = if t.archived {
+            "\x1b[90m[archived]\x1b[0m "
+        } else {
+            ""
+        };
+        let pin = if t.pinned { "\u{2605} " } else { "" };
+        let alias = match &t.alias {
+            Some(alias) => format!(" ({})", alias),
+            None => String::new(),
+        };
+        let max = preview_len.unwrap_or_else(|| match width {
+            // Whatever the id, time and markers leave, less room for "..."
+            Some(width) => {
+                let used = format!("{}{}{}  {}  ", pin, t.id, alias, time)
+                    .chars()
+                    .count()
+                    + if t.archived { "[archived] ".len() } else { 0 };
+                width.saturating_sub(used + 3).max(MIN_PREVIEW_LEN)
+            }
+            None => DEFAULT_PREVIEW_LEN,
+        });
+        let mut preview: String = t.text.chars().take(max).collect();
+        if t.text.chars().count() > max {
+            preview.push_str("...");
+        }
+        println!(
+            "{}\x1b[93m{}\x1b[0m{}  \x1b[90m{}\x1b[0m  {}{}",
+            pin, t.id, alias, time, archived, preview
+        );
+        if let Some(summary) = &t.summary {
+            println!("    \x1b[90m{}\x1b[0m", summary);
+        }
+    }
+
+    Ok(())
+}
+
+/// Widest directory `tx dirs` prints on a terminal before eliding the middle.
+const MAX_DIR_WIDTH: usize = 60;
+
+/// Resolves `--dir` the way `working_dir` was stored: as an absolute path.
+fn absolute_dir(dir: &std::path::Path) -> Result<String> {
+    let dir = std::path::absolute(dir)
+        .with_context(|| format!("Invalid directory: {}", dir.display()))?;
+    Ok(dir.to_string_lossy().into_owned())
+}
+
+fn dirs(config: &config::Config) -> Result<()> {
+    let conn = open_db(config)?;
+    let dirs = db::dirs(&conn)?;
+
+    if dirs.is_empty() {
+        println!("No transcripts yet.");
+        return Ok(());
+    }
+
+    // Only shorten for people; piped output keeps full paths for scripts
+    let shorten = io::stdout().is_terminal();
+    for (dir, count) in dirs {
+        let dir = if shorten {
+            elide_middle(&dir, MAX_DIR_WIDTH)
+        } else {
+            dir
+        };
+        println!("{:>6}  {}", count, dir);
+    }
+
+    Ok(())
+}
+
+/// Shortens `s` to at most `max` characters by replacing its middle with
+/// "...", keeping the start and the (usually more telling) end.
+fn elide_middle(s: &str, max: usize) -> String {
+    let len = s.chars().count();
+    if len <= max {
+        return s.to_string();
+    }
+    let keep = max.saturating_sub(3);
+    let head: String = s.chars().take(keep / 3).collect();
+    let tail: String = s.chars().skip(len - (keep - keep / 3)).collect();
+    format!("{}...{}", head, tail)
+}
+
+/// Describes which transcript an ID argument resolved to, e.g.
+/// "abc1234 (groceries), 2024-01-01 09:30", so a short prefix can be checked.
+fn resolved(t: &db::Transcript, utc: bool) -> String {
+    let alias = match &t.alias {
+        Some(alias) => format!(" ({})", alias),
+        None => String::new(),
+    };
+    format!(
+        "\x1b[93m{}\x1b[0m{}, {}",
+        t.id,
+        alias,
+        display_time(&t.timestamp, utc)
+    )
+}
+
+fn show(cli: &Cli, config: &config::Config, id: &str, play: bool, raw: bool) -> Result<()> {
+    let conn = open_db(config)?;
+
+    let found = db::find_by_prefix(&conn, id)?;
+    // On stderr, and only when it could be a surprise, so `tx show` output
+    // stays pipeable
+    if let Some(t) = &found
+        && t.id != id
+        && !cli.quiet
+    {
+        eprintln!("\x1b[90mShowing\x1b[0m {}", resolved(t, utc(cli, config)));
+    }
+    if let Some(t) = &found
+        && cli.verbose
+    {
+        eprintln!(
+            "\x1b[90mRecorded with tx {}, model {}, from {}\x1b[0m",
+            t.tx_version.as_deref().unwrap_or("unknown"),
+            t.model.as_deref().unwrap_or("unknown"),
+            t.source.as_deref().unwrap_or("unknown")
+        );
+        if let Some(note) = &t.note {
+            eprintln!("\x1b[90mNote:\x1b[0m {}", note);
+        }
+    }
+
+    match found {
+        Some(t) if play => play_along(cli, &t)?,
+        Some(t) if raw => println!("{}", t.raw_text),
+        Some(t) => {
+            println!("{}", t.text);
+        }
+        None => {
+            eprintln!("No transcript found with ID starting with '{}'", id);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// Plays a transcript's saved audio, printing each word as it is spoken.
+///
+/// Falls back to printing the text when audio or word timings are missing.
+fn play_along(cli: &Cli, t: &db::Transcript) -> Result<()> {
+    let words: Vec<&transcribe::Word> = t.segments.iter().flat_map(|s| &s.words).collect();
+    let audio_path = t
+        .audio_path
+        .as_deref()
+        .filter(|p| std::path::Path::new(p).exists());
+
+    let Some(audio_path) = audio_path.filter(|_| !words.is_empty()) else {
+        eprintln!(
+            "\x1b[90m(No saved audio with word timings for this transcript; record with --save-audio)\x1b[0m"
+        );
+        println!("{}", t.text);
+        return Ok(());
+    };
+
+    let samples = audio::read_wav(std::path::Path::new(audio_path), true)?;
+    let playback = audio::play(&samples, cli.output_device.as_deref())?;
+    let start = Instant::now();
+
+    let mut stdout = io::stdout();
+    for (i, word) in words.iter().enumerate() {
+        let at = Duration::from_millis(word.start_ms.max(0) as u64);
+        if let Some(wait) = at.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        let text = if i == 0 {
+            word.text.trim_start()
+        } else {
+            &word.text
+        };
+        write!(stdout, "{}", text)?;
+        stdout.flush()?;
+    }
+    writeln!(stdout)?;
+
+    while !playback.is_done() {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    Ok(())
+}
+
+fn play(cli: &Cli, config: &config::Config, id: &str) -> Result<()> {
+    let conn = open_db(config)?;
+    let Some(t) = db::find_by_prefix(&conn, id)? else {
+        eprintln!("No transcript found with ID starting with '{}'", id);
+        process::exit(1);
+    };
+    let Some(audio_path) = &t.audio_path else {
+        anyhow::bail!(
+            "No audio was saved for {}; record with --save-audio to keep it",
+            t.id
+        );
+    };
+    let audio_path = std::path::Path::new(audio_path);
+    if !audio_path.exists() {
+        anyhow::bail!("The saved audio {} no longer exists", audio_path.display());
+    }
+
+    let samples = audio::read_wav(audio_path, true)?;
+    let stopped = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&stopped);
+    ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed))
+        .context("Failed to install Ctrl-C handler")?;
+
+    if !cli.quiet {
+        eprintln!(
+            "\x1b[90mPlaying\x1b[0m {} \x1b[90m({:.1}s, Ctrl-C to stop)\x1b[0m",
+            resolved(&t, utc(cli, config)),
+            samples.len() as f64 / audio::SAMPLE_RATE as f64
+        );
+    }
+    // Dropping the playback stops it mid-way on Ctrl-C
+    let playback = audio::play(&samples, cli.output_device.as_deref())?;
+    while !playback.is_done() && !stopped.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    Ok(())
+}
+
+fn copy(cli: &Cli, config: &config::Config, id: &str) -> Result<()> {
+    let conn = open_db(config)?;
+
+    match db::find_by_prefix(&conn, id)? {
+        Some(t) => {
+            output::copy_to_clipboard(&t.text)?;
+            eprintln!("Copied {} to clipboard.", resolved(&t, utc(cli, config)));
+        }
+        None => {
+            eprintln!("No transcript found with ID starting with '{}'", id);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_last(config: &config::Config, n: usize, sep: &str) -> Result<()> {
+    let conn = open_db(config)?;
+    let mut transcripts = db::list(&conn, n, &db::Filter::default())?;
+
+    if transcripts.is_empty() {
+        eprintln!("No transcripts yet.");
+        process::exit(1);
+    }
+
+    // Listed newest first; join in the order they were spoken
+    transcripts.reverse();
+    let joined = transcripts
+        .iter()
+        .map(|t| t.text.as_str())
+        .collect::<Vec<_>>()
+        .join(sep);
+
+    output::copy_to_clipboard(&joined)?;
+    if transcripts.len() < n {
+        eprintln!(
+            "Copied all {} transcripts in history to clipboard (asked for {}).",
+            transcripts.len(),
+            n
+        );
+    } else {
+        eprintln!("Copied {} transcripts to clipboard.", transcripts.len());
+    }
+
+    Ok(())
+}
+
+/// Saves the transcripts `ids` joined with `sep` as a new transcript, with
+/// segment times shifted so each follows the one before it.
+fn merge(
+    cli: &Cli,
+    config: &config::Config,
+    ids: &[String],
+    sep: &str,
+    delete_sources: bool,
+) -> Result<()> {
+    let mut conn = open_db(config)?;
+
+    let mut sources: Vec<db::Transcript> = Vec::new();
+    for id in ids {
+        let Some(t) = db::find_by_prefix(&conn, id)? else {
+            eprintln!("No transcript found with ID starting with '{}'", id);
+            process::exit(1);
+        };
+        if sources.iter().any(|s| s.id == t.id) {
+            anyhow::bail!(
+                "'{}' names {} again; each transcript can be merged once",
+                id,
+                t.id
+            );
+        }
+        sources.push(t);
+    }
+
+    let join = |field: fn(&db::Transcript) -> &str| {
+        sources.iter().map(field).collect::<Vec<_>>().join(sep)
+    };
+    let text = join(|t| t.text.as_str());
+    let raw_text = join(|t| t.raw_text.as_str());
+
+    let mut segments = Vec::new();
+    let mut offset_ms = 0;
+    for t in &sources {
+        segments.extend(t.segments.iter().cloned().map(|mut segment| {
+            segment.start_ms += offset_ms;
+            segment.end_ms += offset_ms;
+            for word in &mut segment.words {
+                word.start_ms += offset_ms;
+                word.end_ms += offset_ms;
+            }
+            segment
+        }));
+        offset_ms = segments.last().map_or(offset_ms, |s| s.end_ms);
+    }
+
+    // Kept only when every part agrees
+    let shared = |field: fn(&db::Transcript) -> Option<&str>| {
+        let first = field(&sources[0]);
+        sources
+            .iter()
+            .all(|t| field(t) == first)
+            .then_some(first)
+            .flatten()
+    };
+    let language = shared(|t| t.language.as_deref());
+    let model = shared(|t| t.model.as_deref());
+
+    let timestamp = if utc(cli, config) {
+        Utc::now().fixed_offset()
+    } else {
+        Local::now().fixed_offset()
+    };
+    let id = db::generate_id(&text, &timestamp, id_length(cli, config));
+    let source = format!(
+        "merge:{}",
+        sources
+            .iter()
+            .map(|t| t.id.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+
+    // One transaction, so a failed delete never leaves the text twice
+    let tx = conn.transaction()?;
+    db::save(
+        &tx,
+        &db::NewTranscript {
+            id: &id,
+            text: &text,
+            timestamp,
+            working_dir: &sources[0].working_dir,
+            output_path: None,
+            audio_path: None,
+            segments: &segments,
+            language,
+            summary: None,
+            model,
+            source: Some(&source),
+            raw_text: Some(&raw_text),
+        },
+        config.max_history,
+    )?;
+    if delete_sources {
+        for t in &sources {
+            db::delete(&tx, &t.id)?;
+        }
+    }
+    tx.commit()?;
+
+    if delete_sources {
+        eprintln!(
+            "Merged {} transcripts into \x1b[93m{}\x1b[0m and deleted them",
+            sources.len(),
+            id
+        );
+    } else {
+        eprintln!(
+            "Merged {} transcripts into \x1b[93m{}\x1b[0m",
+            sources.len(),
+            id
+        );
+    }
+
+    Ok(())
+}
+
+fn last(config: &config::Config) -> Result<()> {
+    let conn = open_db(config)?;
+    let transcripts = db::list(&conn, 1, &db::Filter::default())?;
+
+    match transcripts.into_iter().next() {
+        Some(t) => {
+            println!("{}", t.text);
+        }
+        None => {
+            eprintln!("No transcripts yet.");
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn rename(config: &config::Config, id: &str, alias: &str) -> Result<()> {
+    let conn = open_db(config)?;
+
+    match db::find_by_prefix(&conn, id)? {
+        Some(t) => {
+            db::set_alias(&conn, &t.id, alias)?;
+            eprintln!(
+                "Renamed \x1b[93m{}\x1b[0m to \x1b[93m{}\x1b[0m",
+                t.id, alias
+            );
+        }
+        None => {
+            eprintln!("No transcript found with ID starting with '{}'", id);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets a transcript's note, or clears it when `text` is `None`.
+fn note(config: &config::Config, id: &str, text: Option<&str>) -> Result<()> {
+    let conn = open_db(config)?;
+
+    match db::find_by_prefix(&conn, id)? {
+        Some(t) => {
+            db::set_note(&conn, &t.id, text)?;
+            if text.is_some() {
+                eprintln!("Noted \x1b[93m{}\x1b[0m", t.id);
+            } else {
+                eprintln!("Cleared the note on \x1b[93m{}\x1b[0m", t.id);
+            }
+        }
+        None => {
+            eprintln!("No transcript found with ID starting with '{}'", id);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn pin(config: &config::Config, id: &str, pinned: bool) -> Result<()> {
+    let conn = open_db(config)?;
+
+    match db::find_by_prefix(&conn, id)? {
+        Some(t) => {
+            db::set_pinned(&conn, &t.id, pinned)?;
+            if pinned {
+                eprintln!("Pinned \x1b[93m{}\x1b[0m", t.id);
+            } else {
+                eprintln!("Unpinned \x1b[93m{}\x1b[0m", t.id);
+            }
+        }
+        None => {
+            eprintln!("No transcript found with ID starting with '{}'", id);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn diff(config: &config::Config, old: &str, new: &str) -> Result<()> {
+    let conn = open_db(config)?;
+
+    let mut texts = Vec::new();
+    for id in [old, new] {
+        match db::find_by_prefix(&conn, id)? {
+            Some(t) => texts.push(t.text),
+            None => {
+                eprintln!("No transcript found with ID starting with '{}'", id);
+                process::exit(1);
+            }
+        }
+    }
+
+    match text::word_diff(&texts[0], &texts[1]) {
+        Some(diff) => println!("{}", diff),
+        None => eprintln!("No differences."),
+    }
+
+    Ok(())
+}
+
+fn archive(config: &config::Config, id: &str, archived: bool) -> Result<()> {
+    let conn = open_db(config)?;
+
+    match db::find_by_prefix(&conn, id)? {
+        Some(t) => {
+            db::set_archived(&conn, &t.id, archived)?;
+            if archived {
+                eprintln!("Archived \x1b[93m{}\x1b[0m", t.id);
+            } else {
+                eprintln!("Restored \x1b[93m{}\x1b[0m", t.id);
+            }
+        }
+        None => {
+            eprintln!("No transcript found with ID starting with '{}'", id);
+            process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+fn undo(config: &config::Config) -> Result<()> {
+    let conn = open_db(config)?;
+    let transcripts = db::list(&conn, 1, &db::Filter::default())?;
+
+    let Some(t) = transcripts.into_iter().next() else {
+        eprintln!("No transcripts yet.");
+        process::exit(1);
+    };
+
+    db::delete(&conn, &t.id)?;
+    eprintln!("Deleted transcript \x1b[93m{}\x1b[0m", t.id);
+
+    for path in [&t.output_path, &t.audio_path].into_iter().flatten() {
+        match std::fs::remove_file(path) {
+            Ok(()) => eprintln!("Removed {}", path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("\x1b[90m(Could not remove {}: {})\x1b[0m", path, e),
+        }
+    }
+
+    if let Ok(true) = output::clear_clipboard_if(&t.text) {
+        eprintln!("Cleared clipboard.");
+    }
+
+    Ok(())
+}
+
+fn clean(
+    cli: &Cli,
+    config: &config::Config,
+    older_than: Option<DateTime<Local>>,
+    orphans: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let output_dir = std::path::absolute(&cli.output_dir).context("Invalid output directory")?;
+
+    let referenced: std::collections::HashSet<PathBuf> = if orphans {
+        let conn = open_db(config)?;
+        db::all(&conn)?
+            .into_iter()
+            .flat_map(|t| [t.output_path, t.audio_path])
+            .flatten()
+            .map(PathBuf::from)
+            .collect()
+    } else {
+        Default::default()
+    };
+    let cutoff = older_than.map(std::time::SystemTime::from);
+
+    let entries = std::fs::read_dir(&output_dir)
+        .with_context(|| format!("Cannot read {}", output_dir.display()))?;
+    let mut doomed = Vec::new();
+    for path in entries.filter_map(|e| e.ok().map(|e| e.path())) {
+        if !path.is_file() || !output::is_output_file(&path) {
+            continue;
+        }
+        if orphans && referenced.contains(&path) {
+            continue;
+        }
+        if let Some(cutoff) = cutoff {
+            let modified = path.metadata().and_then(|m| m.modified())?;
+            if modified > cutoff {
+                continue;
+            }
+        }
+        doomed.push(path);
+    }
+    doomed.sort();
+
+    if doomed.is_empty() {
+        eprintln!("Nothing to clean in {}.", output_dir.display());
+        return Ok(());
+    }
+    for path in &doomed {
+        println!("{}", path.display());
+    }
+    if dry_run {
+        eprintln!("\n{} file(s) would be removed.", doomed.len());
+        return Ok(());
+    }
+
+    if !confirm(&format!("\nRemove {} file(s)?", doomed.len()))? {
+        eprintln!("Nothing removed.");
+        return Ok(());
+    }
+
+    let mut removed = 0;
+    for path in &doomed {
+        match std::fs::remove_file(path) {
+            Ok(()) => removed += 1,
+            Err(e) => eprintln!(
+                "\x1b[90m(Could not remove {}: {})\x1b[0m",
+                path.display(),
+                e
+            ),
+        }
+    }
+    eprintln!("Removed {} file(s).", removed);
+    Ok(())
+}
+
+/// Asks a yes/no question on stderr; anything but "y" or "yes" means no.
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{} [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn prune(
+    config: &config::Config,
+    cutoff: DateTime<Local>,
+    archive: Option<&std::path::Path>,
+    yes: bool,
+) -> Result<()> {
+    let mut conn = open_db(config)?;
+    let doomed = db::unpinned_before(&conn, cutoff)?;
+
+    if doomed.is_empty() {
+        eprintln!(
+            "No unpinned transcripts before {}.",
+            cutoff.format("%Y-%m-%d %H:%M")
+        );
+        return Ok(());
+    }
+    if !yes
+        && !confirm(&format!(
+            "Delete {} transcript(s) from before {}{}?",
+            doomed.len(),
+            cutoff.format("%Y-%m-%d %H:%M"),
+            if archive.is_some() {
+                " after archiving them"
+            } else {
+                ""
+            }
+        ))?
+    {
+        eprintln!("Nothing pruned.");
+        return Ok(());
+    }
+
+    // Never overwrite an earlier archive; it may hold the only copy
+    if let Some(path) = archive {
+        let file = std::fs::File::create_new(path)
+            .with_context(|| format!("Cannot create archive {}", path.display()))?;
+        let mut out = io::BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut out, &doomed)?;
+        writeln!(out)?;
+        out.flush()
+            .with_context(|| format!("Failed to write archive {}", path.display()))?;
+        eprintln!(
+            "Archived {} transcript(s) to {}",
+            doomed.len(),
+            path.display()
+        );
+    }
+
+    let ids: Vec<&str> = doomed.iter().map(|t| t.id.as_str()).collect();
+    let pruned = db::delete_many(&mut conn, &ids)?;
+    eprintln!("Pruned {} transcript(s).", pruned);
+    Ok(())
+}
+
+fn export(config: &config::Config, redact_paths: bool, utc: bool) -> Result<()> {
+    let conn = open_db(config)?;
+    let mut transcripts = db::all(&conn)?;
+
+    if utc {
+        for t in &mut transcripts {
+            t.timestamp = t.timestamp.with_timezone(&Utc).fixed_offset();
+        }
+    }
+
+    if redact_paths {
+        for t in &mut transcripts {
+            t.redact_paths();
+        }
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    serde_json::to_writer_pretty(&mut out, &transcripts)?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+fn bench(cli: &Cli, file: &std::path::Path) -> Result<()> {
+    let samples = audio::read_wav(file, true)?;
+    if samples.is_empty() {
+        eprintln!("{} contains no audio", file.display());
+        process::exit(Code::NoAudio as i32);
+    }
+    let duration = samples.len() as f64 / audio::SAMPLE_RATE as f64;
+
+    let models = model::local_models()?;
+    if models.is_empty() {
+        eprintln!("No local models found. Run tx once to download the default model.");
+        process::exit(Code::Model as i32);
+    }
+
+    eprintln!(
+        "\x1b[90mBenchmarking {} model(s) on {} ({:.1}s of audio)\x1b[0m\n",
+        models.len(),
+        file.display(),
+        duration
+    );
+
+    let opts = transcribe::Options {
+        language: transcribe::language_option(&cli.lang),
+        ..Default::default()
+    };
+
+    let mut results = Vec::new();
+    for path in &models {
+        let name = model::model_name(path);
+        eprint!("\x1b[90m{}...\x1b[0m", name);
+        io::stderr().flush().ok();
+
+        let start = Instant::now();
+        let ctx = transcribe::load_model(path, true)?;
+        let load = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let text = transcribe::transcribe(&ctx, &samples, &opts)?.text;
+        let decode = start.elapsed().as_secs_f64();
+
+        eprint!("\r\x1b[K");
+        results.push((name, load, decode, text));
+    }
+
+    let width = results.iter().map(|r| r.0.len()).max().unwrap_or(0);
+    println!(
+        "{:<width$}  {:>8}  {:>8}  {:>6}",
+        "MODEL",
+        "LOAD",
+        "DECODE",
+        "RTF",
+        width = width
+    );
+    for (name, load, decode, _) in &results {
+        println!(
+            "{:<width$}  {:>7.2}s  {:>7.2}s  {:>6.2}",
+            name,
+            load,
+            decode,
+            decode / duration,
+            width = width
+        );
+    }
+
+    for (name, _, _, text) in &results {
+        println!("\n\x1b[93m{}\x1b[0m\n{}", name, text);
+    }
+
+    Ok(())
+}
+
+fn warmup(cli: &Cli, decode: bool) -> Result<()> {
+    let model_path = model_path(cli)?;
+
+    let start = Instant::now();
+    let ctx = transcribe::load_model(&model_path, true)?;
+    eprintln!(
+        "Loaded {} in {:.2}s",
+        model_path.display(),
+        start.elapsed().as_secs_f64()
+    );
+
+    if decode {
+        let silence = vec![0.0; audio::SAMPLE_RATE as usize];
+        let start = Instant::now();
+        transcribe::transcribe(&ctx, &silence, &transcribe::Options::default())?;
+        eprintln!("Ran test decode in {:.2}s", start.elapsed().as_secs_f64());
+    }
+
+    Ok(())
+}
+
+/// Finds the model to use, downloading it on first run. `--force-download`
+/// fetches the default model afresh first.
+fn model_path(cli: &Cli) -> Result<PathBuf> {
+    let progress = model::Progress::detect(cli.no_progress);
+    if !cli.force_download {
+        return model::get_model_path(progress);
+    }
+    if std::env::var_os("TX_MODEL_PATH").is_some() {
+        anyhow::bail!("--force-download only replaces the default model; unset TX_MODEL_PATH");
+    }
+    let download = model::Download::default_model()?;
+    download.run(progress)?;
+    Ok(download.path)
+}
+
+/// Existing models at least this big are only replaced after confirmation.
+const CONFIRM_REPLACE_BYTES: u64 = 200_000_000;
+
+fn download(cli: &Cli, name: &str, dry_run: bool, force: bool, yes: bool) -> Result<()> {
+    let download = model::Download::named(name)?;
+    if dry_run {
+        download.print_plan(force);
+        return Ok(());
+    }
+    if let Ok(existing) = std::fs::metadata(&download.path) {
+        if !force {
+            eprintln!(
+                "Already downloaded: {} (--force to download again)",
+                download.path.display()
+            );
+            return Ok(());
+        }
+        if existing.len() >= CONFIRM_REPLACE_BYTES
+            && !yes
+            && !confirm(&format!(
+                "Replace {} ({}MB)?",
+                download.path.display(),
+                existing.len() / 1_000_000
+            ))?
+        {
+            eprintln!("Kept the existing model.");
+            return Ok(());
+        }
+    }
+
+    download.run(model::Progress::detect(cli.no_progress))?;
+    eprintln!(
+        "\n\x1b[92mDownloaded {}\x1b[0m\nUse it with TX_MODEL_PATH={}",
+        name,
+        download.path.display()
+    );
+    Ok(())
+}
+
+fn doctor(config: &config::Config) -> Result<()> {
+    let mut failures = 0;
+    let mut check = |name: &str, result: Result<String>, hint: &str| match result {
+        Ok(detail) => println!("\x1b[92m  ok\x1b[0m  {}: {}", name, detail),
+        Err(e) => {
+            failures += 1;
+            println!("\x1b[91mFAIL\x1b[0m  {}: {:#}", name, e);
+            println!("      \x1b[90m{}\x1b[0m", hint);
+        }
+    };
+
+    check(
+        "Audio input",
+        audio::input_devices().and_then(|(names, default)| match default {
+            Some(default) => Ok(format!("{} ({} device(s) found)", default, names.len())),
+            None if names.is_empty() => anyhow::bail!("no input devices found"),
+            None => anyhow::bail!("{} device(s) found but none is the default", names.len()),
+        }),
+        "Connect a microphone and allow your terminal microphone access in System Settings > Privacy & Security.",
+    );
+
+    check(
+        "Audio output",
+        audio::output_devices().and_then(|(names, default)| match default {
+            Some(default) => Ok(format!("{} (of: {})", default, names.join(", "))),
+            None if names.is_empty() => anyhow::bail!("no output devices found"),
+            None => anyhow::bail!("{} device(s) found but none is the default", names.len()),
+        }),
+        "Only needed for --beep and playback; pick one with --output-device.",
+    );
+
+    check(
+        "Model",
+        model::find_model().and_then(|path| {
+            let path = match path {
+                Some(path) => path,
+                None => anyhow::bail!("not found at {}", model::default_model_path()?.display()),
+            };
+            let size = model::verify(&path)?;
+            Ok(format!("{} ({}MB)", path.display(), size / 1_000_000))
+        }),
+        "Run tx once to download it, or `tx models download base.en`. Delete a corrupt file and download it again.",
+    );
+
+    check(
+        "Clipboard",
+        output::check_clipboard().map(|()| "available".to_string()),
+        "Use --no-clip, or run tx from a desktop session with clipboard access.",
+    );
+
+    // The directory in use, which --data-dir or TX_DATA_DIR may have moved
+    let history_hint = match config::data_dir() {
+        Ok(dir) => format!(
+            "Check permissions on the tx data directory ({}).",
+            dir.display()
+        ),
+        Err(_) => "Point --data-dir or TX_DATA_DIR at a writable directory.".to_string(),
+    };
+    check(
+        "History",
+        open_db(config).and_then(|conn| {
+            db::check_writable(&conn)?;
+            Ok(format!(
+                "{} transcript(s), writable",
+                db::count(
+                    &conn,
+                    &db::Filter {
+                        include_archived: true,
+                        ..Default::default()
+                    }
+                )?
+            ))
+        }),
+        &history_hint,
+    );
+
+    println!(
+        "\x1b[90minfo\x1b[0m  whisper.cpp build: {}",
+        transcribe::system_info().trim()
+    );
+
+    if failures > 0 {
+        eprintln!("\n{} check(s) failed.", failures);
+        process::exit(Code::General as i32);
+    }
+    Ok(())
+}
+
+fn languages() -> Result<()> {
+    println!("{:<6}  detect from the audio", "auto");
+    println!("{:<6}  let the model decide", transcribe::NO_LANGUAGE);
+    for (code, name) in transcribe::languages() {
+        println!("{:<6}  {}", code, name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cutoffs_take_bare_days_and_everything_parse_when_does() {
+        let days = |t: DateTime<Local>| (Local::now() - t).num_hours() / 24;
+        assert_eq!(days(parse_cutoff("30").unwrap()), 30);
+        assert_eq!(days(parse_cutoff("2w").unwrap()), 14);
+        assert_eq!(
+            parse_cutoff("2024-01-01").unwrap(),
+            parse_when("2024-01-01").unwrap()
+        );
+        assert!(parse_cutoff("").is_err());
+        assert!(parse_cutoff("-3").is_err());
+        assert!(parse_cutoff("soon").is_err());
+    }
 }
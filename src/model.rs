@@ -1,44 +1,241 @@
 //! Model path resolution and download for Whisper models.
 
+use crate::error::{Code, ResultExt};
 use anyhow::{Context, Result, bail};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::fs::{self, File};
-use std::io::{self, BufRead, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::debug;
 
 const MODEL_NAME: &str = "ggml-base.en.bin";
 const MODEL_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-base.en.bin";
 const MODEL_SIZE: u64 = 147_964_211; // ~141MB
 
+/// Where `tx models download` fetches `ggml-<name>.bin` from.
+const DOWNLOAD_BASE_URL: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+
+/// Models `tx models download` knows about, with approximate sizes in MiB.
+const KNOWN_MODELS: &[(&str, u64)] = &[
+    ("tiny", 75),
+    ("tiny.en", 75),
+    ("base", 142),
+    ("base.en", 142),
+    ("small", 466),
+    ("small.en", 466),
+    ("medium", 1_500),
+    ("medium.en", 1_500),
+    ("large-v3", 2_900),
+];
+
+/// A model download: where it comes from, where it goes, and how big it is.
+pub struct Download {
+    pub url: String,
+    pub path: PathBuf,
+    /// Expected size in bytes (approximate except for the default model)
+    pub size: u64,
+}
+
+impl Download {
+    /// The download for the default model, as the first-run wizard does it.
+    pub fn default_model() -> Result<Self> {
+        Ok(Download {
+            url: MODEL_URL.to_string(),
+            path: models_dir()?.join(MODEL_NAME),
+            size: MODEL_SIZE,
+        })
+    }
+
+    /// The download for a named model such as `small.en`.
+    pub fn named(name: &str) -> Result<Self> {
+        let Some(&(name, mib)) = KNOWN_MODELS.iter().find(|(n, _)| *n == name) else {
+            let names: Vec<&str> = KNOWN_MODELS.iter().map(|(n, _)| *n).collect();
+            bail!(
+                "Unknown model '{}'. Known models: {}",
+                name,
+                names.join(", ")
+            );
+        };
+        let file = format!("ggml-{}.bin", name);
+        let size = if file == MODEL_NAME {
+            MODEL_SIZE
+        } else {
+            mib * 1024 * 1024
+        };
+        Ok(Download {
+            url: format!("{}/{}", DOWNLOAD_BASE_URL, file),
+            path: models_dir()?.join(file),
+            size,
+        })
+    }
+
+    /// Prints what this download would do, without touching the network.
+    /// With `force`, an existing file would be replaced rather than kept.
+    pub fn print_plan(&self, force: bool) {
+        println!("URL:    {}", self.url);
+        println!("Target: {}", self.path.display());
+        println!("Size:   ~{}MB", self.size / 1_000_000);
+        if self.path.exists() {
+            if force {
+                println!("(Already downloaded; would be replaced)");
+            } else {
+                println!("(Already downloaded; nothing to do)");
+            }
+        }
+    }
+
+    /// Downloads the model into place, creating the models directory first.
+    ///
+    /// Any existing file is only replaced once the new one is complete and
+    /// verified, so a failed download leaves it untouched.
+    pub fn run(&self, progress: Progress) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).context("Failed to create models directory")?;
+        }
+        download_model(&self.url, &self.path, self.size, progress).code(Code::Model)
+    }
+}
+
+/// How download progress is reported on stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// An animated progress bar.
+    Bar { unicode: bool },
+    /// A plain line every 10%, for logs and CI where a bar would be noise.
+    Lines,
+    /// Nothing at all.
+    Hidden,
+}
+
+impl Progress {
+    /// Picks a style for the current terminal: hidden when `disabled`, plain
+    /// lines when stderr isn't a TTY, and an ASCII-only bar when the locale
+    /// or terminal doesn't look like it can draw unicode.
+    pub fn detect(disabled: bool) -> Progress {
+        if disabled {
+            Progress::Hidden
+        } else if !io::stderr().is_terminal() {
+            Progress::Lines
+        } else {
+            Progress::Bar {
+                unicode: supports_unicode(),
+            }
+        }
+    }
+}
+
+fn supports_unicode() -> bool {
+    if std::env::var("TERM").is_ok_and(|t| t == "dumb" || t == "linux") {
+        return false;
+    }
+    // The first of these that is set decides, as with setlocale(3)
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        .is_some_and(|locale| {
+            let locale = locale.to_ascii_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
+
 /// Returns the path to the Whisper model, downloading if necessary.
-pub fn get_model_path() -> Result<PathBuf> {
+pub fn get_model_path(progress: Progress) -> Result<PathBuf> {
+    if let Some(path) = find_model()? {
+        debug!(path = %path.display(), "using model");
+        return Ok(path);
+    }
+
+    // Model not found - offer to download
+    let data_dir = models_dir()?;
+    let model_path = data_dir.join(MODEL_NAME);
+    first_run_wizard(&data_dir, &model_path, progress).code(Code::Model)?;
+
+    Ok(model_path)
+}
+
+/// Returns the path to the Whisper model if it is already present.
+pub fn find_model() -> Result<Option<PathBuf>> {
     // Check environment variable first
     if let Ok(path) = std::env::var("TX_MODEL_PATH") {
         let path = PathBuf::from(path);
         if path.exists() {
-            return Ok(path);
+            return Ok(Some(path));
         }
     }
 
     // Check standard location
-    let data_dir = dirs::data_local_dir()
-        .context("Cannot determine local data directory")?
-        .join("tx")
-        .join("models");
+    let model_path = models_dir()?.join(MODEL_NAME);
 
-    let model_path = data_dir.join(MODEL_NAME);
+    Ok(model_path.exists().then_some(model_path))
+}
 
-    if model_path.exists() {
-        return Ok(model_path);
+/// Checks that a model file looks intact, returning its size in bytes.
+///
+/// The default model's exact size is known; for others only emptiness can
+/// be detected.
+pub fn verify(path: &Path) -> Result<u64> {
+    let size = fs::metadata(path)
+        .with_context(|| format!("Cannot read {}", path.display()))?
+        .len();
+    if size == 0 {
+        bail!("{} is empty", path.display());
+    }
+    if path.file_name().is_some_and(|n| n == MODEL_NAME) && size != MODEL_SIZE {
+        bail!(
+            "{} is {} bytes, expected {} (incomplete download?)",
+            path.display(),
+            size,
+            MODEL_SIZE
+        );
     }
+    Ok(size)
+}
 
-    // Model not found - offer to download
-    first_run_wizard(&data_dir, &model_path)?;
+/// Returns the default model's path in the models directory.
+pub fn default_model_path() -> Result<PathBuf> {
+    Ok(models_dir()?.join(MODEL_NAME))
+}
 
-    Ok(model_path)
+/// Returns the directory where downloaded models are stored.
+fn models_dir() -> Result<PathBuf> {
+    Ok(crate::config::data_dir()?.join("models"))
+}
+
+/// Names a model by its file name, e.g. `ggml-base.en.bin`.
+pub fn model_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
 }
 
-fn first_run_wizard(data_dir: &PathBuf, model_path: &PathBuf) -> Result<()> {
+/// Lists models available locally: `TX_MODEL_PATH` (if set) plus every
+/// `.bin` file in the models directory.
+pub fn local_models() -> Result<Vec<PathBuf>> {
+    let mut models = Vec::new();
+
+    if let Ok(path) = std::env::var("TX_MODEL_PATH") {
+        let path = PathBuf::from(path);
+        if path.exists() {
+            models.push(path);
+        }
+    }
+
+    let data_dir = models_dir()?;
+    if let Ok(entries) = fs::read_dir(&data_dir) {
+        let mut found: Vec<PathBuf> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "bin"))
+            .filter(|p| !models.contains(p))
+            .collect();
+        found.sort();
+        models.extend(found);
+    }
+
+    Ok(models)
+}
+
+fn first_run_wizard(data_dir: &Path, model_path: &Path, progress: Progress) -> Result<()> {
     eprintln!("\n\x1b[93mFirst run setup\x1b[0m");
     eprintln!("tx needs to download the Whisper speech recognition model (~141MB).");
     eprintln!("This only happens once.\n");
@@ -68,19 +265,24 @@ fn first_run_wizard(data_dir: &PathBuf, model_path: &PathBuf) -> Result<()> {
     fs::create_dir_all(data_dir).context("Failed to create models directory")?;
 
     // Download with progress bar
-    download_model(model_path)?;
+    download_model(MODEL_URL, model_path, MODEL_SIZE, progress)?;
 
     eprintln!("\n\x1b[92mModel downloaded successfully!\x1b[0m\n");
 
     Ok(())
 }
 
-fn download_model(model_path: &PathBuf) -> Result<()> {
+fn download_model(
+    url: &str,
+    model_path: &Path,
+    expected_size: u64,
+    progress: Progress,
+) -> Result<()> {
     eprintln!();
 
     let client = reqwest::blocking::Client::new();
     let response = client
-        .get(MODEL_URL)
+        .get(url)
         .send()
         .context("Failed to connect to Hugging Face")?;
 
@@ -88,19 +290,44 @@ fn download_model(model_path: &PathBuf) -> Result<()> {
         bail!("Download failed: HTTP {}", response.status());
     }
 
-    let total_size = response.content_length().unwrap_or(MODEL_SIZE);
+    // Without a Content-Length the expected size only drives the progress
+    // display; it is approximate, so completeness can't be checked against it
+    let content_length = response.content_length();
+    let total_size = content_length.unwrap_or(expected_size);
 
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
+    let pb = match progress {
+        Progress::Bar { unicode } => {
+            let pb = ProgressBar::new(total_size);
+            let style = ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                .unwrap()
+                .progress_chars("#>-");
+            pb.set_style(if unicode {
+                style
+            } else {
+                style.tick_chars("|/-\\ ")
+            });
+            pb
+        }
+        Progress::Lines | Progress::Hidden => ProgressBar::hidden(),
+    };
+    let mut next_percent = 10;
 
-    // Download to temp file first, then rename
-    let temp_path = model_path.with_extension("bin.tmp");
-    let mut file = File::create(&temp_path).context("Failed to create temp file")?;
+    // Download to temp file first, then move into place
+    let temp_name = temp_file_name(model_path);
+    let temp_path = match std::env::var_os("TX_TMP_DIR") {
+        Some(dir) => {
+            let dir = PathBuf::from(dir);
+            fs::create_dir_all(&dir).context("Failed to create TX_TMP_DIR")?;
+            dir.join(temp_name)
+        }
+        None => model_path.with_file_name(temp_name),
+    };
+    if let Some(dir) = temp_path.parent() {
+        remove_stale_temp_files(dir, model_path);
+    }
+    let mut file = File::create(&temp_path)
+        .with_context(|| format!("Failed to create temp file {}", temp_path.display()))?;
 
     let mut downloaded: u64 = 0;
     let mut reader = response;
@@ -110,9 +337,19 @@ fn download_model(model_path: &PathBuf) -> Result<()> {
         match std::io::Read::read(&mut reader, &mut buffer) {
             Ok(0) => break,
             Ok(n) => {
-                file.write_all(&buffer[..n])?;
+                if let Err(e) = file.write_all(&buffer[..n]) {
+                    let _ = fs::remove_file(&temp_path);
+                    bail!("Failed to write {}: {}", temp_path.display(), e);
+                }
                 downloaded += n as u64;
                 pb.set_position(downloaded);
+                if progress == Progress::Lines && total_size > 0 {
+                    let percent = (downloaded * 100 / total_size).min(100);
+                    if percent >= next_percent {
+                        eprintln!("Downloaded {}% ({}MB)", percent, downloaded / 1_000_000);
+                        next_percent = (percent / 10 + 1) * 10;
+                    }
+                }
             }
             Err(e) => {
                 let _ = fs::remove_file(&temp_path);
@@ -123,8 +360,139 @@ fn download_model(model_path: &PathBuf) -> Result<()> {
 
     pb.finish_with_message("done");
 
-    // Rename temp file to final path
-    fs::rename(&temp_path, model_path).context("Failed to finalize model file")?;
+    file.sync_all().context("Failed to flush model file")?;
+    drop(file);
+
+    // Verify the download is complete before moving it into place
+    if let Some(total_size) = content_length
+        && downloaded != total_size
+    {
+        let _ = fs::remove_file(&temp_path);
+        bail!(
+            "Download incomplete: got {} of {} bytes. Please try again.",
+            downloaded,
+            total_size
+        );
+    }
+
+    move_file(&temp_path, model_path).context("Failed to finalize model file")?;
 
     Ok(())
 }
+
+/// Name of the file a download of `model_path` is written to: the model's
+/// full file name plus this process's pid, so multi-dot names keep every
+/// part and two downloads of the same model never share a temp file.
+fn temp_file_name(model_path: &Path) -> std::ffi::OsString {
+    let mut name = model_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.tmp", std::process::id()));
+    name
+}
+
+/// How long a download's temp file can go unwritten before it counts as
+/// left behind by a run that was killed or crashed.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Removes temp files in `dir` from earlier downloads of `model_path` that
+/// never finished. One still being written by another tx is recent, so it
+/// is left alone.
+fn remove_stale_temp_files(dir: &Path, model_path: &Path) {
+    let Some(model) = model_path.file_name().and_then(|n| n.to_str()) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name();
+        let is_temp = name
+            .to_str()
+            .and_then(|n| {
+                n.strip_prefix(model)?
+                    .strip_prefix('.')?
+                    .strip_suffix(".tmp")
+            })
+            .is_some_and(|pid| !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()));
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t.elapsed().is_ok_and(|age| age > STALE_TEMP_AGE));
+        if is_temp && stale {
+            debug!(path = %entry.path().display(), "removing stale download temp file");
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Moves a file, falling back to copy + remove when `from` and `to` are on
+/// different filesystems. The copy lands beside `to` first and is renamed
+/// over it, so `to` never exists half-written.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let staging = to.with_extension("bin.partial");
+            if let Err(e) = fs::copy(from, &staging).and_then(|_| fs::rename(&staging, to)) {
+                let _ = fs::remove_file(&staging);
+                return Err(e);
+            }
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_file_name_keeps_every_dot_and_adds_the_pid() {
+        let pid = std::process::id();
+        assert_eq!(
+            temp_file_name(Path::new("/models/ggml-base.en.bin")),
+            format!("ggml-base.en.bin.{pid}.tmp").as_str()
+        );
+        assert_eq!(
+            temp_file_name(Path::new("ggml-large-v3-turbo-q5_0.bin")),
+            format!("ggml-large-v3-turbo-q5_0.bin.{pid}.tmp").as_str()
+        );
+        assert_ne!(
+            temp_file_name(Path::new("a/ggml-tiny.bin")),
+            temp_file_name(Path::new("a/ggml-tiny.en.bin"))
+        );
+    }
+
+    #[test]
+    fn stale_temp_files_are_removed_before_a_download() {
+        let dir = std::env::temp_dir().join(format!("tx-stale-tmp-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let model = dir.join("ggml-base.en.bin");
+        let old = std::time::SystemTime::now() - STALE_TEMP_AGE * 2;
+        let touch = |name: &str, stale: bool| {
+            let file = File::create(dir.join(name)).unwrap();
+            if stale {
+                file.set_modified(old).unwrap();
+            }
+        };
+        touch("ggml-base.en.bin.123.tmp", true);
+        touch("ggml-base.en.bin.456.tmp", false);
+        touch("ggml-base.bin.123.tmp", true);
+        touch("ggml-base.en.bin.notes.tmp", true);
+
+        remove_stale_temp_files(&dir, &model);
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            left,
+            [
+                "ggml-base.bin.123.tmp",
+                "ggml-base.en.bin.456.tmp",
+                "ggml-base.en.bin.notes.tmp"
+            ]
+        );
+    }
+}
@@ -1,31 +1,1209 @@
-//! Output handling: file save and clipboard.
+//! Output handling: delivering a finished transcript to one or more sinks.
 
-use anyhow::{Context, Result};
+use crate::audio;
+use crate::db;
+use crate::transcribe::{Segment, Transcription};
+use anyhow::{Context, Result, bail};
 use arboard::Clipboard;
-use chrono::Local;
-use std::fs;
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
-/// Saves transcription text to a timestamped file.
+/// A destination for a finished transcript.
 ///
-/// Returns the path to the saved file.
-pub fn save_to_file(text: &str, output_dir: &Path) -> Result<PathBuf> {
-    fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+/// Sinks run in the order given, so later sinks can use what earlier ones
+/// produced (e.g. the `Db` sink records the path written by `File`).
+#[derive(Debug)]
+pub enum Sink {
+    /// Timestamped file in the given directory
+    File(PathBuf, FileOptions),
+    /// File at exactly this path; an existing file is an error unless
+    /// `overwrite` is set
+    ExactFile {
+        path: PathBuf,
+        opts: FileOptions,
+        overwrite: bool,
+    },
+    /// Timestamped WAV of the recorded audio in the given directory
+    Audio(PathBuf, audio::WavDepth),
+    /// Transcript history database
+    Db(db::Settings),
+    /// An existing transcript (and its saved text file, if any) that the
+    /// new text is appended to, in place of `File` and `Db`
+    AppendTo {
+        id: String,
+        file: Option<PathBuf>,
+        store: db::Settings,
+    },
+    /// System clipboard, optionally formatted as a `> ` quote
+    Clipboard { quote: bool },
+    /// The transcript's ID on the system clipboard, in place of `Clipboard`
+    ClipboardId,
+    /// The terminal's clipboard via an OSC 52 escape, which reaches the local
+    /// machine over SSH; optionally formatted as a `> ` quote
+    Osc52 { quote: bool },
+    /// Timestamped entry appended to a running log file
+    AppendLog(PathBuf),
+    /// Shell command receiving the text on stdin
+    Exec(String),
+    /// Named pipe read by another process; skipped if nobody is reading
+    Fifo(PathBuf),
+    /// Desktop notification showing the start of the text
+    Notify,
+    /// Text on stdout (bare in quiet mode, fenced with a summary otherwise).
+    /// `newline: false` omits the trailing newline in quiet mode; the other
+    /// fields apply only to the fenced, non-quiet form.
+    Stdout {
+        quiet: bool,
+        newline: bool,
+        layout: Layout,
+        confidence: bool,
+        /// Cut the displayed text after this many characters
+        max_chars: Option<usize>,
+        /// Merge segments less than this far apart (ms) in segment layouts
+        merge_gap_ms: Option<i64>,
+    },
+    /// A single `JsonTranscript` object on stdout
+    Json,
+}
+
+/// Encoding options for saved transcript files.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileOptions {
+    pub format: FileFormat,
+    /// Write `\r\n` line endings instead of `\n` (text files only)
+    pub crlf: bool,
+    /// Prefix the file with a UTF-8 byte order mark (text files only)
+    pub bom: bool,
+}
+
+/// What a saved transcript file contains.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FileFormat {
+    /// The transcript text (`.txt`)
+    #[default]
+    Txt,
+    /// A `JsonTranscript` object, including segments (`.json`)
+    Json,
+}
+
+/// A signal that a transcription has finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Alert {
+    /// Ring the terminal bell
+    Bell,
+    /// Flash the terminal by briefly switching it to reverse video
+    Flash,
+    /// Show a desktop notification
+    Notify,
+}
+
+/// How long `Alert::Flash` keeps the terminal in reverse video.
+const FLASH_LENGTH: std::time::Duration = std::time::Duration::from_millis(150);
+
+/// Gives the signal for `alert`.
+pub fn alert(alert: Alert) -> Result<()> {
+    match alert {
+        Alert::Bell => {
+            let mut terminal = terminal()?;
+            terminal.write_all(b"\x07")?;
+            terminal.flush()?;
+        }
+        Alert::Flash => {
+            let mut terminal = terminal()?;
+            terminal.write_all(b"\x1b[?5h")?;
+            terminal.flush()?;
+            std::thread::sleep(FLASH_LENGTH);
+            terminal.write_all(b"\x1b[?5l")?;
+            terminal.flush()?;
+        }
+        Alert::Notify => notify("Transcription finished")?,
+    }
+    Ok(())
+}
+
+/// How the non-quiet stdout rendering lays out a transcript.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// The transcript text as saved
+    #[default]
+    Plain,
+    /// One Whisper segment per line
+    Lines,
+    /// One segment per line, prefixed with its `[mm:ss]` start time
+    Timestamps,
+}
+
+/// Word probabilities below these are shown in yellow and red respectively
+/// by `--show-confidence`.
+const CONFIDENCE_MEDIUM: f32 = 0.8;
+const CONFIDENCE_LOW: f32 = 0.5;
+
+impl Layout {
+    /// Renders the delivery for display. Segment layouts fall back to the
+    /// plain text when there are no segments.
+    ///
+    /// With `confidence`, words are colored by Whisper's confidence in them.
+    /// That view is built from the decoded words, so it doesn't reflect
+    /// `--tidy` or other post-processing of the text. With `merge_gap_ms`,
+    /// the segment layouts join segments separated by less than that.
+    fn render(self, delivery: &Delivery, confidence: bool, merge_gap_ms: Option<i64>) -> String {
+        let merged;
+        let segments = match merge_gap_ms {
+            Some(gap) if self != Layout::Plain => {
+                merged = crate::text::merge_segments(&delivery.segments, gap);
+                &merged
+            }
+            _ => &delivery.segments,
+        };
+        let has_words = segments.iter().any(|s| !s.words.is_empty());
+        let segment_text = |s: &Segment| {
+            if confidence && has_words {
+                colored_words(s)
+            } else {
+                s.text.clone()
+            }
+        };
+
+        if segments.is_empty() || (self == Layout::Plain && !(confidence && has_words)) {
+            return delivery.text.clone();
+        }
+        if self == Layout::Plain {
+            return segments
+                .iter()
+                .map(segment_text)
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+        segments
+            .iter()
+            .map(|s| match self {
+                Layout::Timestamps => {
+                    let secs = s.start_ms / 1000;
+                    format!("[{:02}:{:02}] {}", secs / 60, secs % 60, segment_text(s))
+                }
+                _ => segment_text(s),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Cuts rendered text after `max` visible characters, noting how many were
+/// left out. Color escapes don't count, and one left open is reset.
+fn truncate_display(text: &str, max: usize) -> String {
+    let mut out = String::new();
+    let mut shown = 0;
+    let mut hidden = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // An SGR sequence: ESC [ ... m
+            let escape: String = chars.by_ref().take_while(|&c| c != 'm').collect();
+            if hidden == 0 {
+                out.push(c);
+                out.push_str(&escape);
+                out.push('m');
+            }
+        } else if shown < max {
+            out.push(c);
+            shown += 1;
+        } else {
+            hidden += 1;
+        }
+    }
+    if hidden > 0 {
+        out.push_str(&format!(
+            "\x1b[0m\u{2026} \x1b[90m({} more chars)\x1b[0m",
+            hidden
+        ));
+    }
+    out
+}
+
+/// Joins a segment's words, coloring the less certain ones.
+fn colored_words(segment: &Segment) -> String {
+    let mut out = String::new();
+    for word in &segment.words {
+        let color = if word.probability < CONFIDENCE_LOW {
+            Some("\x1b[91m")
+        } else if word.probability < CONFIDENCE_MEDIUM {
+            Some("\x1b[93m")
+        } else {
+            None
+        };
+        // Keep the leading space outside the color
+        let text = word.text.trim_start();
+        out.push_str(&word.text[..word.text.len() - text.len()]);
+        match color {
+            Some(color) => out.push_str(&format!("{}{}\x1b[0m", color, text)),
+            None => out.push_str(text),
+        }
+    }
+    out.trim_start().to_string()
+}
+
+/// A transcript being delivered, plus what the sinks have produced so far.
+#[derive(Debug)]
+pub struct Delivery {
+    pub text: String,
+    /// Untruncated text when `--max-words`/`--first-sentence` shortened
+    /// `text`; history stores this, every other sink gets `text`
+    pub full_text: Option<String>,
+    pub segments: Vec<Segment>,
+    /// Language code Whisper decoded as
+    pub language: Option<String>,
+    /// Heuristic summary stored in history under `--summary`
+    pub summary: Option<String>,
+    /// File name of the model that produced the transcript
+    pub model: Option<String>,
+    /// How the audio was captured: "mic", "stdin" or "file:<path>"
+    pub source: Option<String>,
+    /// Whisper's text before `--tidy`, `--strip-filler` and the like
+    pub raw_text: Option<String>,
+    /// 16kHz mono audio the transcript came from
+    pub audio: Vec<f32>,
+    /// Local time, or UTC under `--utc`; file names and logs follow it
+    pub timestamp: DateTime<FixedOffset>,
+    pub working_dir: String,
+    pub id: String,
+    pub file: Option<PathBuf>,
+    pub audio_file: Option<PathBuf>,
+    pub copied: bool,
+    /// The ID, rather than the text, went to the clipboard
+    pub copied_id: bool,
+    /// Position within a split recording, used to number output files
+    pub part: Option<usize>,
+}
+
+impl Delivery {
+    /// Creates a delivery timestamped now (in UTC if `utc`), with an ID of
+    /// `id_length` characters assigned up front so every sink can use it.
+    pub fn new(
+        transcription: Transcription,
+        audio: Vec<f32>,
+        working_dir: String,
+        id_length: usize,
+        utc: bool,
+    ) -> Self {
+        let timestamp = if utc {
+            Utc::now().fixed_offset()
+        } else {
+            Local::now().fixed_offset()
+        };
+        Self {
+            id: db::generate_id(&transcription.text, &timestamp, id_length),
+            text: transcription.text,
+            full_text: None,
+            segments: transcription.segments,
+            language: transcription.language,
+            summary: None,
+            model: None,
+            source: None,
+            raw_text: None,
+            audio,
+            timestamp,
+            working_dir,
+            file: None,
+            audio_file: None,
+            copied: false,
+            copied_id: false,
+            part: None,
+        }
+    }
+}
+
+/// The JSON shape of a transcript, as printed by `--json` and saved by
+/// `--format json`.
+///
+/// This is a stable contract for integrations: fields may be added but are
+/// never renamed, retyped, or removed.
+#[derive(Debug, Serialize)]
+pub struct JsonTranscript<'a> {
+    /// Short transcript ID, usable with `tx show`/`tx copy`
+    pub id: Option<&'a str>,
+    pub text: &'a str,
+    /// RFC 3339 timestamp with offset
+    pub timestamp: String,
+    pub working_dir: &'a str,
+    /// Path of the saved file, if one was written
+    pub file: Option<String>,
+    pub segments: &'a [Segment],
+    /// Language code Whisper decoded as
+    pub language: Option<&'a str>,
+}
+
+impl<'a> From<&'a Delivery> for JsonTranscript<'a> {
+    fn from(d: &'a Delivery) -> Self {
+        Self {
+            id: Some(&d.id),
+            text: &d.text,
+            timestamp: d.timestamp.to_rfc3339(),
+            working_dir: &d.working_dir,
+            file: d.file.as_ref().map(|p| p.display().to_string()),
+            segments: &d.segments,
+            language: d.language.as_deref(),
+        }
+    }
+}
+
+/// Returns the JSON Schema (draft 2020-12) for `JsonTranscript`.
+///
+/// `$defs` also covers the segment and word objects inside it, the
+/// `{"error": ...}` object `--json` prints on failure, and the array of
+/// stored transcripts `tx export` writes. Keep this in step with the
+/// serialized types when fields are added.
+pub fn json_schema() -> serde_json::Value {
+    let nullable_string = serde_json::json!({ "type": ["string", "null"] });
+    serde_json::json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "tx transcript",
+        "description": "Printed by --json and saved by --format json",
+        "type": "object",
+        "required": ["id", "text", "timestamp", "working_dir", "file", "segments"],
+        "properties": {
+            "id": {
+                "type": ["string", "null"],
+                "description": "Short transcript ID, usable with tx show/copy"
+            },
+            "text": { "type": "string" },
+            "timestamp": {
+                "type": "string",
+                "format": "date-time",
+                "description": "RFC 3339 with offset"
+            },
+            "working_dir": { "type": "string" },
+            "file": {
+                "type": ["string", "null"],
+                "description": "Path of the saved file, if one was written"
+            },
+            "segments": { "type": "array", "items": { "$ref": "#/$defs/segment" } },
+            "language": {
+                "type": ["string", "null"],
+                "description": "Language code Whisper decoded as"
+            }
+        },
+        "$defs": {
+            "segment": {
+                "type": "object",
+                "required": ["start_ms", "end_ms", "text"],
+                "properties": {
+                    "start_ms": { "type": "integer", "description": "From the clip start" },
+                    "end_ms": { "type": "integer" },
+                    "text": { "type": "string" },
+                    "words": {
+                        "type": "array",
+                        "items": { "$ref": "#/$defs/word" },
+                        "description": "Present when word timestamps were recorded"
+                    }
+                }
+            },
+            "word": {
+                "type": "object",
+                "required": ["start_ms", "end_ms", "text", "probability"],
+                "properties": {
+                    "start_ms": { "type": "integer" },
+                    "end_ms": { "type": "integer" },
+                    "text": { "type": "string", "description": "Including any leading space" },
+                    "probability": { "type": "number", "minimum": 0, "maximum": 1 }
+                }
+            },
+            "error": {
+                "type": "object",
+                "description": "Printed by --json instead of a transcript on failure",
+                "required": ["error"],
+                "properties": { "error": { "type": "string" } }
+            },
+            "export": {
+                "type": "array",
+                "description": "Written by tx export and tx prune --archive, oldest first",
+                "items": {
+                    "type": "object",
+                    "required": [
+                        "id", "text", "timestamp", "working_dir", "output_path",
+                        "archived", "audio_path", "segments", "alias", "pinned", "language",
+                        "summary", "tx_version", "model", "source", "raw_text", "note"
+                    ],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "text": { "type": "string" },
+                        "timestamp": { "type": "string", "format": "date-time" },
+                        "working_dir": { "type": "string" },
+                        "output_path": nullable_string,
+                        "archived": { "type": "boolean" },
+                        "audio_path": nullable_string,
+                        "segments": { "type": "array", "items": { "$ref": "#/$defs/segment" } },
+                        "alias": nullable_string,
+                        "pinned": { "type": "boolean" },
+                        "language": nullable_string,
+                        "summary": nullable_string,
+                        "tx_version": {
+                            "type": ["string", "null"],
+                            "description": "tx version that saved it; \"unknown\" if older"
+                        },
+                        "model": {
+                            "type": ["string", "null"],
+                            "description": "Model file name; \"unknown\" if older"
+                        },
+                        "source": {
+                            "type": ["string", "null"],
+                            "description": "mic, stdin, file:<path> or merge:<id>,<id>... (from tx merge); older transcripts say mic"
+                        },
+                        "raw_text": {
+                            "type": "string",
+                            "description": "Whisper's text before post-processing such as --tidy"
+                        },
+                        "note": {
+                            "type": ["string", "null"],
+                            "description": "Set with tx note"
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Prints `{"error": message}` to stdout for `--json` callers.
+pub fn print_json_error(message: &str) {
+    println!("{}", serde_json::json!({ "error": message }));
+}
+
+impl Sink {
+    /// Returns true if a failure in this sink should not abort the run.
+    pub fn is_optional(&self) -> bool {
+        self.is_clipboard() || matches!(self, Sink::Fifo(_) | Sink::Notify | Sink::Osc52 { .. })
+    }
+
+    pub fn is_clipboard(&self) -> bool {
+        matches!(self, Sink::Clipboard { .. } | Sink::ClipboardId)
+    }
 
-    let timestamp = Local::now().format("%Y-%m-%d-%H%M%S");
-    let filename = format!("tx-{}.txt", timestamp);
-    let path = output_dir.join(filename);
+    /// Directory this sink writes into, if any.
+    pub fn output_dir(&self) -> Option<&Path> {
+        match self {
+            Sink::File(dir, _) | Sink::Audio(dir, _) => Some(dir),
+            Sink::ExactFile { path, .. } => path.parent(),
+            _ => None,
+        }
+    }
+
+    /// Human-readable sink name for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sink::File(..) | Sink::ExactFile { .. } => "File save",
+            Sink::Audio(..) => "Audio save",
+            Sink::Db(_) | Sink::AppendTo { .. } => "History",
+            Sink::Clipboard { .. } | Sink::ClipboardId => "Clipboard copy",
+            Sink::Osc52 { .. } => "Terminal clipboard copy",
+            Sink::AppendLog(_) => "Log append",
+            Sink::Exec(_) => "Command",
+            Sink::Fifo(_) => "FIFO write",
+            Sink::Notify => "Notification",
+            Sink::Stdout { .. } | Sink::Json => "Output",
+        }
+    }
+
+    /// Delivers the transcript to this sink.
+    pub fn deliver(&self, delivery: &mut Delivery) -> Result<()> {
+        match self {
+            Sink::File(dir, opts) => {
+                delivery.file = Some(save_to_file(delivery, dir, opts)?);
+            }
+            Sink::ExactFile {
+                path,
+                opts,
+                overwrite,
+            } => {
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir).context("Failed to create output directory")?;
+                }
+                write_file(delivery, path, opts, *overwrite)?;
+                delivery.file = Some(path.clone());
+            }
+            Sink::Audio(dir, depth) => {
+                fs::create_dir_all(dir).context("Failed to create output directory")?;
+                let path = dir.join(file_name(&delivery.timestamp, delivery.part, "wav"));
+                audio::write_wav(&path, &delivery.audio, *depth)?;
+                delivery.audio_file = Some(path);
+            }
+            Sink::Db(store) => {
+                let conn = db::open(store)?;
+                let file = delivery.file.as_ref().map(|p| p.display().to_string());
+                let audio_file = delivery
+                    .audio_file
+                    .as_ref()
+                    .map(|p| p.display().to_string());
+                db::save(
+                    &conn,
+                    &db::NewTranscript {
+                        id: &delivery.id,
+                        text: delivery.full_text.as_ref().unwrap_or(&delivery.text),
+                        timestamp: delivery.timestamp,
+                        working_dir: &delivery.working_dir,
+                        output_path: file.as_deref(),
+                        audio_path: audio_file.as_deref(),
+                        segments: &delivery.segments,
+                        language: delivery.language.as_deref(),
+                        summary: delivery.summary.as_deref(),
+                        model: delivery.model.as_deref(),
+                        source: delivery.source.as_deref(),
+                        raw_text: delivery.raw_text.as_deref(),
+                    },
+                    store.max_history,
+                )?;
+            }
+            Sink::AppendTo { id, file, store } => {
+                let conn = db::open(store)?;
+                let text = delivery.full_text.as_ref().unwrap_or(&delivery.text);
+                let raw_text = delivery.raw_text.as_ref().unwrap_or(text);
+                db::append(&conn, id, text, raw_text)?;
+                delivery.id = id.clone();
+
+                // Only plain text files can be extended in place
+                if let Some(file) = file
+                    .as_ref()
+                    .filter(|f| f.extension().is_some_and(|e| e == "txt"))
+                {
+                    let mut out = OpenOptions::new()
+                        .append(true)
+                        .open(file)
+                        .with_context(|| format!("Failed to open {}", file.display()))?;
+                    write!(out, "\n{}", delivery.text)
+                        .with_context(|| format!("Failed to append to {}", file.display()))?;
+                    delivery.file = Some(file.clone());
+                }
+            }
+            Sink::Clipboard { quote } => {
+                if *quote {
+                    copy_to_clipboard(&quote_text(&delivery.text))?;
+                } else {
+                    copy_to_clipboard(&delivery.text)?;
+                }
+                delivery.copied = true;
+            }
+            Sink::Osc52 { quote } => {
+                if *quote {
+                    copy_osc52(&quote_text(&delivery.text))?;
+                } else {
+                    copy_osc52(&delivery.text)?;
+                }
+                delivery.copied = true;
+            }
+            Sink::ClipboardId => {
+                copy_to_clipboard(&delivery.id)?;
+                delivery.copied_id = true;
+            }
+            Sink::AppendLog(path) => append_to_log(delivery, path)?,
+            Sink::Exec(command) => exec(command, delivery)?,
+            Sink::Fifo(path) => write_fifo(&delivery.text, path)?,
+            Sink::Notify => notify(&delivery.text)?,
+            Sink::Stdout {
+                quiet: true,
+                newline,
+                ..
+            } => {
+                let mut stdout = std::io::stdout();
+                if *newline {
+                    writeln!(stdout, "{}", delivery.text)?;
+                } else {
+                    write!(stdout, "{}", delivery.text)?;
+                }
+                stdout.flush()?;
+            }
+            Sink::Json => println!(
+                "{}",
+                serde_json::to_string(&JsonTranscript::from(&*delivery))?
+            ),
+            Sink::Stdout {
+                quiet: false,
+                layout,
+                confidence,
+                max_chars,
+                merge_gap_ms,
+                ..
+            } => {
+                if let Some(file) = &delivery.file {
+                    eprintln!(
+                        "\n\x1b[92mSaved:\x1b[0m {} \x1b[90m({})\x1b[0m",
+                        file.display(),
+                        delivery.id
+                    );
+                }
+                let mut text = layout.render(delivery, *confidence, *merge_gap_ms);
+                if let Some(max) = max_chars {
+                    text = truncate_display(&text, *max);
+                }
+                println!("\n---\n{}\n---\n", text);
+                if delivery.copied {
+                    eprintln!("\x1b[90mCopied to clipboard.\x1b[0m");
+                }
+                if delivery.copied_id {
+                    eprintln!(
+                        "\x1b[92mCopied ID\x1b[0m \x1b[93m{}\x1b[0m \x1b[90mto clipboard.\x1b[0m",
+                        delivery.id
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Per-file results of a run over several `--input` files (`--report`),
+/// written a row at a time so a crash still leaves the rows for the files
+/// already done.
+///
+/// Rows are JSON Lines, or CSV when the path ends in `.csv`, with the
+/// fields `file`, `status` ("ok" or "failed"), `ids` and `outputs` (the
+/// transcripts saved from the file and their output files) and `error`.
+pub struct Report {
+    file: File,
+    csv: bool,
+}
+
+impl Report {
+    pub fn create(path: &Path) -> Result<Report> {
+        let csv = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        let mut file = File::create(path)
+            .with_context(|| format!("Failed to create report {}", path.display()))?;
+        if csv {
+            writeln!(file, "file,status,ids,outputs,error")?;
+        }
+        Ok(Report { file, csv })
+    }
+
+    /// Adds the row for `input`: the transcripts `saved` from it (ID and
+    /// output file), or the `error` it failed with.
+    pub fn row(
+        &mut self,
+        input: &Path,
+        saved: &[(String, Option<PathBuf>)],
+        error: Option<&anyhow::Error>,
+    ) -> Result<()> {
+        let status = if error.is_some() { "failed" } else { "ok" };
+        let ids: Vec<&str> = saved.iter().map(|(id, _)| id.as_str()).collect();
+        let outputs: Vec<String> = saved
+            .iter()
+            .filter_map(|(_, file)| file.as_ref().map(|f| f.display().to_string()))
+            .collect();
+        let error = error.map(|e| format!("{:#}", e));
+        let line = if self.csv {
+            [
+                input.display().to_string(),
+                status.to_string(),
+                ids.join(";"),
+                outputs.join(";"),
+                error.unwrap_or_default(),
+            ]
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>()
+            .join(",")
+        } else {
+            serde_json::json!({
+                "file": input.display().to_string(),
+                "status": status,
+                "ids": ids,
+                "outputs": outputs,
+                "error": error,
+            })
+            .to_string()
+        };
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Quotes a CSV field if it needs it, doubling any quotes inside.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
 
-    fs::write(&path, text).context("Failed to write transcription file")?;
+/// Checks that `dir` is (or can be created as) a writable directory, so a
+/// bad `--output-dir` fails before recording rather than after.
+pub fn check_output_dir(dir: &Path) -> Result<()> {
+    if dir.exists() && !dir.is_dir() {
+        bail!("Output directory {} is not a directory", dir.display());
+    }
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Cannot create output directory {}", dir.display()))?;
+
+    let probe = dir.join(format!(".tx-write-test-{}", std::process::id()));
+    fs::write(&probe, b"")
+        .with_context(|| format!("Output directory {} is not writable", dir.display()))?;
+    let _ = fs::remove_file(&probe);
+
+    Ok(())
+}
+
+/// Saves a transcript to a timestamped file in the chosen format.
+///
+/// The delivery's `part` adds a numeric suffix so chunks of one recording
+/// don't collide. Returns the path to the saved file.
+pub fn save_to_file(delivery: &Delivery, output_dir: &Path, opts: &FileOptions) -> Result<PathBuf> {
+    fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+
+    let ext = match opts.format {
+        FileFormat::Txt => "txt",
+        FileFormat::Json => "json",
+    };
+    let path = output_dir.join(file_name(&delivery.timestamp, delivery.part, ext));
+    write_file(delivery, &path, opts, true)?;
 
     Ok(path)
 }
 
-/// Copies text to the system clipboard.
+/// Writes a transcript to `path` in the chosen format, refusing to replace
+/// an existing file unless `overwrite` is set.
+fn write_file(delivery: &Delivery, path: &Path, opts: &FileOptions, overwrite: bool) -> Result<()> {
+    let contents = match opts.format {
+        FileFormat::Txt => encode(&delivery.text, opts),
+        FileFormat::Json => {
+            let mut json = JsonTranscript::from(delivery);
+            json.file = Some(path.display().to_string());
+            let mut bytes = serde_json::to_vec_pretty(&json)?;
+            bytes.push(b'\n');
+            bytes
+        }
+    };
+
+    let mut file = if overwrite {
+        File::create(path)
+    } else {
+        File::create_new(path)
+    }
+    .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(&contents)
+        .context("Failed to write transcription file")?;
+
+    Ok(())
+}
+
+/// Builds `tx-<timestamp>[-<part>].<ext>` for output files.
+fn file_name(timestamp: &DateTime<FixedOffset>, part: Option<usize>, ext: &str) -> String {
+    let timestamp = timestamp.format("%Y-%m-%d-%H%M%S");
+    match part {
+        Some(n) => format!("tx-{}-{}.{}", timestamp, n, ext),
+        None => format!("tx-{}.{}", timestamp, ext),
+    }
+}
+
+/// Returns true if `path` looks like a file tx wrote (`tx-*.txt|json|wav`).
+pub fn is_output_file(path: &Path) -> bool {
+    let named = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with("tx-"));
+    let ext = path.extension().and_then(|e| e.to_str());
+    named && matches!(ext, Some("txt" | "json" | "wav"))
+}
+
+/// Applies line-ending and BOM options to text bound for a file.
+fn encode(text: &str, opts: &FileOptions) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    if opts.bom {
+        bytes.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    if opts.crlf {
+        bytes.extend_from_slice(text.replace("\r\n", "\n").replace('\n', "\r\n").as_bytes());
+    } else {
+        bytes.extend_from_slice(text.as_bytes());
+    }
+    bytes
+}
+
+/// Appends a timestamped entry to a log file, creating it if needed.
+fn append_to_log(delivery: &Delivery, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create log directory")?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path.display()))?;
+
+    let timestamp = delivery.timestamp.format("%Y-%m-%d %H:%M:%S");
+    writeln!(file, "[{}] {}", timestamp, delivery.text).context("Failed to append to log file")?;
+
+    Ok(())
+}
+
+/// Runs a shell command with the transcript on stdin.
+///
+/// The id and file path (when one was saved) are exposed as `TX_ID` and
+/// `TX_FILE`.
+/// The command's stdout goes to stderr so tx's own stdout stays parseable.
+fn exec(command: &str, delivery: &Delivery) -> Result<()> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(std::io::stderr());
+    cmd.env("TX_ID", &delivery.id);
+    if let Some(file) = &delivery.file {
+        cmd.env("TX_FILE", file);
+    }
+
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", command))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(delivery.text.as_bytes())
+            .context("Failed to write to command")?;
+    }
+
+    let status = child.wait().context("Failed to wait for command")?;
+    if !status.success() {
+        bail!("Command '{}' exited with {}", command, status);
+    }
+
+    Ok(())
+}
+
+/// Writes text plus a newline to a named pipe without ever blocking on open.
+///
+/// The pipe is opened non-blocking, so if no process has it open for
+/// reading this fails immediately instead of hanging. Once open, the write
+/// itself blocks normally until the reader has taken the data.
+#[cfg(unix)]
+fn write_fifo(text: &str, path: &Path) -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+    use std::os::unix::io::AsRawFd;
+
+    let meta = fs::metadata(path).with_context(|| format!("{} does not exist", path.display()))?;
+    if !meta.file_type().is_fifo() {
+        bail!(
+            "{} is not a named pipe (create one with mkfifo)",
+            path.display()
+        );
+    }
+
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(file) => file,
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+            bail!("no process is reading {}", path.display())
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+    };
+
+    // Back to blocking mode so large transcripts aren't cut off by EAGAIN
+    unsafe {
+        let fd = file.as_raw_fd();
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+    }
+
+    writeln!(file, "{}", text).context("Failed to write to FIFO")?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_fifo(_text: &str, _path: &Path) -> Result<()> {
+    bail!("--fifo is only supported on Unix")
+}
+
+/// Longest notification body, in characters; desktops truncate or wrap
+/// badly beyond a few lines.
+const NOTIFY_MAX_CHARS: usize = 200;
+
+/// Shows the text as a desktop notification, cut to `NOTIFY_MAX_CHARS`.
+fn notify(text: &str) -> Result<()> {
+    let mut body: String = text.chars().take(NOTIFY_MAX_CHARS).collect();
+    if text.chars().count() > NOTIFY_MAX_CHARS {
+        body.push_str("...");
+    }
+    notify_rust::Notification::new()
+        .summary("tx")
+        .body(&body)
+        .show()
+        .context("Failed to show notification (is a notification daemon running?)")?;
+    Ok(())
+}
+
+/// Formats text as a Markdown-style quote.
+fn quote_text(text: &str) -> String {
+    text.lines()
+        .map(|line| format!("> {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Checks that the system clipboard can be opened.
+pub fn check_clipboard() -> Result<()> {
+    Clipboard::new().context("Failed to access clipboard")?;
+    Ok(())
+}
+
+/// Copies text to the system clipboard as plain UTF-8.
+///
+/// The clipboard is cleared first so no stale rich-text or image
+/// representations survive alongside the new text.
 pub fn copy_to_clipboard(text: &str) -> Result<()> {
     let mut clipboard = Clipboard::new().context("Failed to access clipboard")?;
+    clipboard.clear().context("Failed to clear clipboard")?;
     clipboard
         .set_text(text)
         .context("Failed to copy to clipboard")?;
     Ok(())
 }
+
+/// Copies text through the terminal with an OSC 52 escape sequence, which
+/// terminals that support it put on the clipboard of the machine they run
+/// on, even when tx is on the far end of an SSH session.
+fn copy_osc52(text: &str) -> Result<()> {
+    use base64::Engine;
+
+    let seq = format!(
+        "\x1b]52;c;{}\x07",
+        base64::engine::general_purpose::STANDARD.encode(text)
+    );
+    // tmux only passes escapes through to the outer terminal when wrapped,
+    // with each ESC doubled
+    let seq = if std::env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", seq.replace('\x1b', "\x1b\x1b"))
+    } else {
+        seq
+    };
+    let mut terminal = terminal()?;
+    terminal.write_all(seq.as_bytes())?;
+    terminal.flush()?;
+    Ok(())
+}
+
+/// The terminal for escape sequences (OSC 52, bells): the controlling tty,
+/// so they work while stdout and stderr are piped, or else stderr if that
+/// is a terminal.
+fn terminal() -> Result<Box<dyn Write>> {
+    if let Ok(tty) = OpenOptions::new().write(true).open("/dev/tty") {
+        return Ok(Box::new(tty));
+    }
+    if std::io::IsTerminal::is_terminal(&std::io::stderr()) {
+        return Ok(Box::new(std::io::stderr()));
+    }
+    bail!("not attached to a terminal")
+}
+
+/// Checks that there is a terminal to send OSC 52 to.
+pub fn check_osc52() -> Result<()> {
+    terminal().map(drop)
+}
+
+/// Returns the text on the system clipboard.
+pub fn clipboard_text() -> Result<String> {
+    let mut clipboard = Clipboard::new().context("Failed to access clipboard")?;
+    clipboard.get_text().context("The clipboard holds no text")
+}
+
+/// Clears the clipboard if it still holds `text`.
+///
+/// Returns true if the clipboard was cleared.
+pub fn clear_clipboard_if(text: &str) -> Result<bool> {
+    let mut clipboard = Clipboard::new().context("Failed to access clipboard")?;
+    if clipboard.get_text().ok().as_deref() != Some(text) {
+        return Ok(false);
+    }
+    clipboard.clear().context("Failed to clear clipboard")?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcribe::Word;
+    use std::collections::BTreeSet;
+
+    fn delivery() -> Delivery {
+        let segments = vec![
+            Segment {
+                start_ms: 0,
+                end_ms: 1500,
+                text: "Buy milk.".to_string(),
+                words: vec![Word {
+                    start_ms: 0,
+                    end_ms: 400,
+                    text: "Buy".to_string(),
+                    probability: 0.9,
+                }],
+            },
+            Segment {
+                start_ms: 61000,
+                end_ms: 62000,
+                text: "And eggs.".to_string(),
+                words: Vec::new(),
+            },
+        ];
+        let transcription = Transcription {
+            text: "Buy milk. And eggs.".to_string(),
+            segments,
+            language: Some("en".to_string()),
+            ..Transcription::default()
+        };
+        Delivery::new(transcription, Vec::new(), "/work".to_string(), 8, true)
+    }
+
+    fn keys(value: &serde_json::Value) -> BTreeSet<&str> {
+        value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn layouts_render_segments() {
+        let d = delivery();
+        assert_eq!(Layout::Plain.render(&d, false, None), "Buy milk. And eggs.");
+        assert_eq!(
+            Layout::Lines.render(&d, false, None),
+            "Buy milk.\nAnd eggs."
+        );
+        assert_eq!(
+            Layout::Timestamps.render(&d, false, None),
+            "[00:00] Buy milk.\n[01:01] And eggs."
+        );
+        // A gap over a minute merges only when that's allowed
+        assert_eq!(
+            Layout::Lines.render(&d, false, Some(60_000)),
+            "Buy milk. And eggs."
+        );
+    }
+
+    #[test]
+    fn layouts_fall_back_to_text_without_segments() {
+        let mut d = delivery();
+        d.segments.clear();
+        d.text = "Tidied text.".to_string();
+        for layout in [Layout::Plain, Layout::Lines, Layout::Timestamps] {
+            assert_eq!(layout.render(&d, true, None), "Tidied text.");
+        }
+    }
+
+    #[test]
+    fn confidence_colors_uncertain_words() {
+        let mut d = delivery();
+        d.segments[0].words = [(" Buy", 0.9), (" milk", 0.6), (" now", 0.2)]
+            .iter()
+            .map(|&(text, probability)| Word {
+                start_ms: 0,
+                end_ms: 0,
+                text: text.to_string(),
+                probability,
+            })
+            .collect();
+        assert_eq!(
+            Layout::Lines.render(&d, true, None).lines().next(),
+            Some("Buy \x1b[93mmilk\x1b[0m \x1b[91mnow\x1b[0m")
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a desktop clipboard"]
+    fn clipboard_round_trips_plain_text() {
+        // On X11 the copied text is served only while some clipboard
+        // handle is alive
+        let _keep = Clipboard::new().unwrap();
+        let text = "Grüße, \"tx\" \u{2014} line one\nline two";
+        copy_to_clipboard(text).unwrap();
+        assert_eq!(clipboard_text().unwrap(), text);
+        assert!(!clear_clipboard_if("something else").unwrap());
+        assert!(clear_clipboard_if(text).unwrap());
+        assert!(clipboard_text().is_err() || clipboard_text().unwrap().is_empty());
+    }
+
+    #[test]
+    fn json_matches_its_schema() {
+        let d = delivery();
+        let json = serde_json::to_value(JsonTranscript::from(&d)).unwrap();
+        let schema = json_schema();
+
+        assert_eq!(keys(&json), keys(&schema["properties"]));
+        for required in schema["required"].as_array().unwrap() {
+            assert!(json.get(required.as_str().unwrap()).is_some(), "{required}");
+        }
+        let segment = &json["segments"][0];
+        assert_eq!(
+            keys(segment),
+            keys(&schema["$defs"]["segment"]["properties"])
+        );
+        assert_eq!(
+            keys(&segment["words"][0]),
+            keys(&schema["$defs"]["word"]["properties"])
+        );
+        assert!(json["segments"][1].get("words").is_none());
+        assert!(DateTime::parse_from_rfc3339(json["timestamp"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn json_segments_round_trip() {
+        let d = delivery();
+        let json = serde_json::to_string(&JsonTranscript::from(&d)).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let segments: Vec<Segment> = serde_json::from_value(value["segments"].clone()).unwrap();
+        assert_eq!(value["id"], d.id.as_str());
+        assert_eq!(value["text"], d.text.as_str());
+        assert_eq!(value["language"], "en");
+        assert_eq!(segments.len(), d.segments.len());
+        for (got, want) in segments.iter().zip(&d.segments) {
+            assert_eq!((got.start_ms, got.end_ms), (want.start_ms, want.end_ms));
+            assert_eq!(got.text, want.text);
+            assert_eq!(got.words.len(), want.words.len());
+        }
+    }
+
+    #[test]
+    fn reports_write_a_row_per_file() {
+        let dir = std::env::temp_dir();
+        let saved = vec![("a1".to_string(), Some(PathBuf::from("/out/a1.txt")))];
+        let failure = anyhow::anyhow!("bad, \"header\"");
+
+        let path = dir.join(format!("tx-report-{}.jsonl", std::process::id()));
+        let mut report = Report::create(&path).unwrap();
+        report.row(Path::new("a.wav"), &saved, None).unwrap();
+        report.row(Path::new("b.wav"), &[], Some(&failure)).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let rows: Vec<serde_json::Value> = text
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["status"], "ok");
+        assert_eq!(rows[0]["ids"][0], "a1");
+        assert_eq!(rows[0]["outputs"][0], "/out/a1.txt");
+        assert!(rows[0]["error"].is_null());
+        assert_eq!(rows[1]["status"], "failed");
+        assert_eq!(rows[1]["error"], "bad, \"header\"");
+
+        let path = dir.join(format!("tx-report-{}.csv", std::process::id()));
+        let mut report = Report::create(&path).unwrap();
+        report.row(Path::new("a.wav"), &saved, None).unwrap();
+        report.row(Path::new("b.wav"), &[], Some(&failure)).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            text,
+            "file,status,ids,outputs,error\n\
+             a.wav,ok,a1,/out/a1.txt,\n\
+             b.wav,failed,,,\"bad, \"\"header\"\"\"\n"
+        );
+    }
+}
@@ -0,0 +1,622 @@
+//! Post-processing applied to transcribed text.
+
+use crate::transcribe::Segment;
+use similar::{ChangeTag, TextDiff};
+use std::ops::Range;
+
+/// Joins each segment to the one before it when the pause between them is
+/// under `max_gap_ms`, so Whisper's mid-sentence splits read as one line.
+/// A merged segment keeps the first one's start and the last one's end.
+pub fn merge_segments(segments: &[Segment], max_gap_ms: i64) -> Vec<Segment> {
+    let mut merged: Vec<Segment> = Vec::new();
+    for segment in segments {
+        match merged.last_mut() {
+            Some(prev) if segment.start_ms - prev.end_ms < max_gap_ms => {
+                if !segment.text.starts_with(char::is_whitespace) {
+                    prev.text.push(' ');
+                }
+                prev.text.push_str(&segment.text);
+                prev.words.extend(segment.words.iter().cloned());
+                prev.end_ms = segment.end_ms;
+            }
+            _ => merged.push(segment.clone()),
+        }
+    }
+    merged
+}
+
+/// Marker inserted by `label_turns` at each presumed change of speaker.
+const TURN_LABEL: &str = "Speaker ?:";
+
+/// How far (ms) a segment boundary may sit from a silence and still count.
+const TURN_TOLERANCE_MS: i64 = 500;
+
+/// Capitalizes the first letter of each sentence and ends the text with a
+/// period if it has no terminal punctuation.
+///
+/// Deliberately conservative: only lowercase sentence-initial letters are
+/// changed, and existing punctuation and casing elsewhere are left alone.
+/// As in `first_sentence`, punctuation only ends a sentence when whitespace
+/// or the end follows (past any closing quote or bracket), so "example.com"
+/// is untouched; a word with an earlier period, like "e.g.", doesn't end
+/// one either.
+pub fn tidy(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 1);
+    let mut sentence_start = true;
+    let mut word_has_period = false;
+
+    for (i, c) in text.char_indices() {
+        if sentence_start && c.is_alphabetic() {
+            out.extend(c.to_uppercase());
+            sentence_start = false;
+            continue;
+        }
+        // Closing quotes and brackets may sit between the two
+        let rest = text[i + c.len_utf8()..].trim_start_matches(['"', '\'', ')']);
+        let at_boundary = rest.chars().next().is_none_or(char::is_whitespace);
+        if matches!(c, '.' | '!' | '?') && at_boundary {
+            sentence_start = !(c == '.' && word_has_period);
+        } else if !c.is_whitespace() && !matches!(c, '"' | '\'' | '(' | ')') {
+            sentence_start = false;
+        }
+        if c.is_whitespace() {
+            word_has_period = false;
+        } else if c == '.' {
+            word_has_period = true;
+        }
+        out.push(c);
+    }
+
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    if out.chars().last().is_some_and(|c| c.is_alphanumeric()) {
+        out.push('.');
+    }
+
+    out
+}
+
+/// Returns the text through its first sentence-ending `.`, `!` or `?`, or
+/// all of it if there is none. Punctuation only ends a sentence when
+/// followed by whitespace or the end, so "3.5" stays whole.
+pub fn first_sentence(text: &str) -> &str {
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let at_boundary = chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+        if matches!(c, '.' | '!' | '?') && at_boundary {
+            return &text[..i + c.len_utf8()];
+        }
+    }
+    text
+}
+
+/// Returns the text through its `n`th word, keeping the original spacing.
+pub fn first_words(text: &str, n: usize) -> &str {
+    let mut words = 0;
+    let mut in_word = false;
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() {
+            if in_word && words == n {
+                return &text[..i];
+            }
+            in_word = false;
+        } else if !in_word {
+            in_word = true;
+            words += 1;
+            if words > n {
+                return text[..i].trim_end();
+            }
+        }
+    }
+    text
+}
+
+/// Lowercases a word and drops everything but letters and digits, so
+/// "Out." and "out" compare equal.
+fn normalize_word(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Removes `phrase` from the end of `text`, ignoring case and punctuation,
+/// along with any punctuation left dangling before it. Returns `None` if the
+/// text doesn't end with the phrase.
+pub fn strip_trailing_phrase(text: &str, phrase: &str) -> Option<String> {
+    let target: Vec<String> = phrase
+        .split_whitespace()
+        .map(normalize_word)
+        .filter(|w| !w.is_empty())
+        .collect();
+    if target.is_empty() {
+        return None;
+    }
+
+    // Words with their byte offsets, skipping pure punctuation like "-"
+    let words: Vec<(usize, String)> = text
+        .split_whitespace()
+        .map(|w| {
+            (
+                w.as_ptr() as usize - text.as_ptr() as usize,
+                normalize_word(w),
+            )
+        })
+        .filter(|(_, w)| !w.is_empty())
+        .collect();
+    if words.len() < target.len() {
+        return None;
+    }
+
+    let tail = &words[words.len() - target.len()..];
+    if tail.iter().map(|(_, w)| w).ne(target.iter()) {
+        return None;
+    }
+
+    let kept = text[..tail[0].0].trim_end();
+    let kept = kept.trim_end_matches([',', ';', ':', '-']);
+    Some(kept.trim_end().to_string())
+}
+
+/// Counts the words `strip_trailing_phrase` matches on, skipping pure
+/// punctuation.
+fn word_count(text: &str) -> usize {
+    text.split_whitespace()
+        .filter(|w| !normalize_word(w).is_empty())
+        .count()
+}
+
+/// Removes `phrase` from the end of `segments` the way
+/// `strip_trailing_phrase` does for text, dropping segments it empties and
+/// the word timings it covers. Returns false, changing nothing, if the
+/// segments don't end with the phrase.
+pub fn strip_trailing_phrase_segments(segments: &mut Vec<Segment>, phrase: &str) -> bool {
+    let joined: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+    if strip_trailing_phrase(&joined.join(" "), phrase).is_none() {
+        return false;
+    }
+
+    // The phrase's words, consumed from the end one segment at a time
+    let mut remaining: Vec<&str> = phrase
+        .split_whitespace()
+        .filter(|w| !normalize_word(w).is_empty())
+        .collect();
+    while let Some(last) = segments.last_mut()
+        && !remaining.is_empty()
+    {
+        let count = word_count(&last.text);
+        if count <= remaining.len() {
+            remaining.truncate(remaining.len() - count);
+            segments.pop();
+            continue;
+        }
+        last.text = strip_trailing_phrase(&last.text, &remaining.join(" ")).unwrap_or_default();
+        let mut dropped = 0;
+        while dropped < remaining.len()
+            && let Some(word) = last.words.pop()
+        {
+            if !normalize_word(&word.text).is_empty() {
+                dropped += 1;
+            }
+        }
+        remaining.clear();
+    }
+
+    // Punctuation that led into the phrase, now dangling at the end
+    if let Some(last) = segments.last_mut() {
+        last.text = last
+            .text
+            .trim_end_matches([',', ';', ':', '-'])
+            .trim_end()
+            .to_string();
+        if last.text.is_empty() {
+            segments.pop();
+        }
+    }
+    true
+}
+
+/// Fillers `strip_filler` removes unless the config lists its own.
+pub const DEFAULT_FILLERS: &[&str] = &[
+    "um", "umm", "uh", "uhh", "uhm", "er", "erm", "ah", "hmm", "you know",
+];
+
+/// Removes filler words and phrases (matched whole, ignoring case and
+/// punctuation), then repairs what they leave behind: the comma that set a
+/// filler off is dropped, a full stop on a filler moves to the word before
+/// it, and a capitalized filler that began a sentence passes its capital on.
+/// "So, um, we should, uh, ship it. Um, tomorrow." becomes "So we should
+/// ship it. Tomorrow."
+pub fn strip_filler(text: &str, fillers: &[String]) -> String {
+    let phrases: Vec<Vec<String>> = fillers
+        .iter()
+        .map(|f| {
+            f.split_whitespace()
+                .map(normalize_word)
+                .filter(|w| !w.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|p| !p.is_empty())
+        .collect();
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let words: Vec<String> = tokens.iter().map(|t| normalize_word(t)).collect();
+
+    let mut out: Vec<String> = Vec::new();
+    let mut capitalize = false;
+    let mut i = 0;
+    while i < tokens.len() {
+        let matched = phrases
+            .iter()
+            .filter(|p| words.get(i..i + p.len()) == Some(&p[..]))
+            .map(Vec::len)
+            .max();
+        let Some(n) = matched else {
+            let word = tokens[i];
+            out.push(if capitalize {
+                capitalize_first(word)
+            } else {
+                word.to_string()
+            });
+            capitalize = false;
+            i += 1;
+            continue;
+        };
+
+        let first = tokens[i];
+        let last = tokens[i + n - 1];
+        let sentence_start = out.last().is_none_or(|w| w.ends_with(['.', '!', '?']));
+        if let Some(prev) = out.last_mut()
+            && !sentence_start
+        {
+            let end = last.chars().last().filter(|c| matches!(c, '.' | '!' | '?'));
+            if end.is_some() || last.ends_with(',') {
+                let kept = prev.trim_end_matches([',', ';', ':']).len();
+                prev.truncate(kept);
+            }
+            if let Some(end) = end {
+                prev.push(end);
+            }
+        }
+        if sentence_start && first.starts_with(char::is_uppercase) {
+            capitalize = true;
+        }
+        i += n;
+    }
+
+    out.join(" ")
+}
+
+/// Uppercases the first character of a word.
+fn capitalize_first(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Most sentences `summarize` returns, including the first.
+const SUMMARY_SENTENCES: usize = 3;
+
+/// Common words that say nothing about what a text is about, ignored when
+/// scoring sentences for `summarize`.
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "all", "also", "an", "and", "are", "as", "at", "be", "been", "but", "by", "can",
+    "do", "for", "from", "get", "go", "had", "has", "have", "he", "her", "his", "i", "if", "in",
+    "into", "is", "it", "its", "just", "like", "me", "my", "no", "not", "of", "on", "or", "our",
+    "out", "really", "she", "so", "some", "that", "the", "their", "them", "then", "there", "they",
+    "this", "to", "up", "us", "very", "was", "we", "were", "what", "when", "which", "will", "with",
+    "would", "you", "your",
+];
+
+/// Splits text into sentences at `.`, `!` or `?` followed by whitespace or
+/// the end (so "3.5" stays whole), trimming each.
+fn sentences(text: &str) -> Vec<&str> {
+    let mut out = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let at_boundary = chars.peek().is_none_or(|(_, next)| next.is_whitespace());
+        if matches!(c, '.' | '!' | '?') && at_boundary {
+            out.push(text[start..i + c.len_utf8()].trim());
+            start = i + c.len_utf8();
+        }
+    }
+    out.push(text[start..].trim());
+    out.retain(|s| !s.is_empty());
+    out
+}
+
+/// Picks a few representative sentences: the first, plus the ones whose
+/// words occur most often across the whole text (ignoring stop words),
+/// kept in their original order.
+///
+/// A plain word-frequency heuristic, deterministic and entirely local.
+/// Returns `None` when the text is too short to need a summary.
+pub fn summarize(text: &str) -> Option<String> {
+    let sentences = sentences(text);
+    if sentences.len() <= SUMMARY_SENTENCES {
+        return None;
+    }
+
+    let content_words = |s: &str| -> Vec<String> {
+        s.split_whitespace()
+            .map(normalize_word)
+            .filter(|w| !w.is_empty() && !STOP_WORDS.contains(&w.as_str()))
+            .collect()
+    };
+    let mut frequency: std::collections::HashMap<String, usize> = Default::default();
+    for word in sentences.iter().flat_map(|s| content_words(s)) {
+        *frequency.entry(word).or_default() += 1;
+    }
+
+    // Average frequency, so long sentences don't win just by being long;
+    // ties go to the earlier sentence
+    let mut scored: Vec<(usize, f64)> = sentences
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, s)| {
+            let words = content_words(s);
+            let total: usize = words.iter().map(|w| frequency[w]).sum();
+            (i, total as f64 / words.len().max(1) as f64)
+        })
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let mut picked: Vec<usize> = std::iter::once(0)
+        .chain(scored.iter().map(|(i, _)| *i).take(SUMMARY_SENTENCES - 1))
+        .collect();
+    picked.sort_unstable();
+    Some(
+        picked
+            .iter()
+            .map(|&i| sentences[i])
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Renders a word-level diff from `old` to `new` in `git diff --word-diff`
+/// style: `[-removed-]` in red and `{+added+}` in green.
+///
+/// Returns `None` if the texts are identical.
+pub fn word_diff(old: &str, new: &str) -> Option<String> {
+    if old == new {
+        return None;
+    }
+
+    let diff = TextDiff::from_words(old, new);
+    let mut out = String::new();
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Equal => out.push_str(change.value()),
+            ChangeTag::Delete => out.push_str(&format!("\x1b[91m[-{}-]\x1b[0m", change.value())),
+            ChangeTag::Insert => out.push_str(&format!("\x1b[92m{{+{}+}}\x1b[0m", change.value())),
+        }
+    }
+    Some(out)
+}
+
+/// Starts a new `Speaker ?:` line wherever a segment boundary falls in one
+/// of `silences_ms`.
+///
+/// This is a pause-based heuristic, not speaker diarization: it can't tell
+/// who is talking, only that a long pause often means a change of speaker.
+pub fn label_turns(segments: &[Segment], silences_ms: &[Range<i64>]) -> String {
+    let mut out = String::new();
+
+    for (i, segment) in segments.iter().enumerate() {
+        let turn = match i.checked_sub(1).map(|prev| &segments[prev]) {
+            None => true,
+            Some(prev) => {
+                let boundary = (prev.end_ms + segment.start_ms) / 2;
+                silences_ms.iter().any(|gap| {
+                    gap.start - TURN_TOLERANCE_MS <= boundary
+                        && boundary <= gap.end + TURN_TOLERANCE_MS
+                })
+            }
+        };
+
+        if turn {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            out.push_str(TURN_LABEL);
+        }
+        out.push(' ');
+        out.push_str(&segment.text);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcribe::Word;
+
+    fn segment(start_ms: i64, end_ms: i64, text: &str) -> Segment {
+        Segment {
+            start_ms,
+            end_ms,
+            text: text.to_string(),
+            words: Vec::new(),
+        }
+    }
+
+    fn texts(segments: &[Segment]) -> Vec<&str> {
+        segments.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    #[test]
+    fn merge_joins_segments_across_short_gaps() {
+        let segments = [
+            segment(0, 1000, "We should"),
+            segment(1200, 2000, " ship it"),
+            segment(2100, 3000, "tomorrow."),
+            segment(5000, 6000, "Anyway."),
+        ];
+        let merged = merge_segments(&segments, 500);
+        assert_eq!(texts(&merged), ["We should ship it tomorrow.", "Anyway."]);
+        assert_eq!((merged[0].start_ms, merged[0].end_ms), (0, 3000));
+        assert_eq!((merged[1].start_ms, merged[1].end_ms), (5000, 6000));
+    }
+
+    #[test]
+    fn merge_keeps_segments_at_the_gap_apart() {
+        let segments = [segment(0, 1000, "One."), segment(1500, 2000, "Two.")];
+        assert_eq!(texts(&merge_segments(&segments, 500)), ["One.", "Two."]);
+        assert!(merge_segments(&[], 500).is_empty());
+    }
+
+    #[test]
+    fn summarize_leaves_short_texts_alone() {
+        assert_eq!(summarize(""), None);
+        assert_eq!(summarize("One. Two. Three."), None);
+    }
+
+    #[test]
+    fn summarize_keeps_the_first_and_most_typical_sentences() {
+        let text = "Notes from the planning call. The release slips a week. \
+                    Weather was nice. The release needs the release notes. \
+                    Lunch was late. The notes go out with the release.";
+        assert_eq!(
+            summarize(text).as_deref(),
+            Some(
+                "Notes from the planning call. The release needs the release notes. \
+                 The notes go out with the release."
+            )
+        );
+        // "3.5" isn't a sentence break
+        let versions = "Version 3.5 is out. It fixes bugs. It adds bugs. Bugs bugs bugs.";
+        assert!(
+            summarize(versions)
+                .unwrap()
+                .starts_with("Version 3.5 is out. ")
+        );
+    }
+
+    fn default_fillers() -> Vec<String> {
+        DEFAULT_FILLERS.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn strip_filler_repairs_punctuation() {
+        assert_eq!(
+            strip_filler(
+                "So, um, we should, uh, ship it. Um, tomorrow.",
+                &default_fillers()
+            ),
+            "So we should ship it. Tomorrow."
+        );
+        assert_eq!(
+            strip_filler("It works, you know.", &default_fillers()),
+            "It works."
+        );
+    }
+
+    #[test]
+    fn strip_filler_matches_whole_words() {
+        let fillers = default_fillers();
+        assert_eq!(
+            strip_filler("The drum hummed.", &fillers),
+            "The drum hummed."
+        );
+        assert_eq!(strip_filler("UM, HMM. Done", &fillers), "Done");
+        assert_eq!(
+            strip_filler("Basically it's fine.", &["basically".to_string()]),
+            "It's fine."
+        );
+    }
+
+    #[test]
+    fn word_diff_marks_changed_words() {
+        assert_eq!(word_diff("same text", "same text"), None);
+        assert_eq!(
+            word_diff("buy the milk", "buy oat milk").as_deref(),
+            Some("buy \x1b[91m[-the-]\x1b[0m\x1b[92m{+oat+}\x1b[0m milk")
+        );
+        assert_eq!(
+            word_diff("done", "done now").as_deref(),
+            Some("done\x1b[92m{+ +}\x1b[0m\x1b[92m{+now+}\x1b[0m")
+        );
+    }
+
+    #[test]
+    fn tidy_capitalizes_sentences() {
+        assert_eq!(
+            tidy("hello there. how are you"),
+            "Hello there. How are you."
+        );
+        assert_eq!(tidy("really? yes! ok"), "Really? Yes! Ok.");
+        assert_eq!(
+            tidy("she said \"stop.\" then left"),
+            "She said \"stop.\" Then left."
+        );
+    }
+
+    #[test]
+    fn tidy_leaves_existing_punctuation() {
+        assert_eq!(tidy("Done."), "Done.");
+        assert_eq!(tidy("what now?  "), "What now?");
+        assert_eq!(tidy(""), "");
+    }
+
+    #[test]
+    fn tidy_ignores_periods_inside_words() {
+        assert_eq!(tidy("see example.com today"), "See example.com today.");
+        assert_eq!(tidy("version 3.5 is out"), "Version 3.5 is out.");
+        assert_eq!(
+            tidy("bring snacks, e.g. chips"),
+            "Bring snacks, e.g. chips."
+        );
+        assert_eq!(tidy("ask, i.e. call him"), "Ask, i.e. call him.");
+    }
+
+    #[test]
+    fn stop_phrase_leaves_segments() {
+        let mut segments = vec![
+            segment(0, 1000, "Buy milk,"),
+            segment(1500, 2500, "over and out."),
+        ];
+        assert!(strip_trailing_phrase_segments(
+            &mut segments,
+            "over and out"
+        ));
+        assert_eq!(texts(&segments), ["Buy milk"]);
+    }
+
+    #[test]
+    fn stop_phrase_split_across_segments() {
+        let mut segments = vec![
+            segment(0, 1000, "Buy milk. Over"),
+            segment(1500, 2500, "and out."),
+        ];
+        segments[0].words = ["Buy", " milk.", " Over"]
+            .iter()
+            .map(|text| Word {
+                start_ms: 0,
+                end_ms: 0,
+                text: text.to_string(),
+                probability: 1.0,
+            })
+            .collect();
+        assert!(strip_trailing_phrase_segments(
+            &mut segments,
+            "over and out"
+        ));
+        assert_eq!(texts(&segments), ["Buy milk."]);
+        assert_eq!(segments[0].words.len(), 2);
+    }
+
+    #[test]
+    fn stop_phrase_missing_changes_nothing() {
+        let mut segments = vec![segment(0, 1000, "Over and out, then more.")];
+        assert!(!strip_trailing_phrase_segments(
+            &mut segments,
+            "over and out"
+        ));
+        assert_eq!(texts(&segments), ["Over and out, then more."]);
+    }
+}
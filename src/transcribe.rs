@@ -1,11 +1,22 @@
 //! Whisper transcription via whisper-rs.
 
+use crate::error::{Code, ResultExt};
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ffi::c_void;
+use std::ops::Range;
 use std::os::raw::c_char;
 use std::path::Path;
-use std::sync::Once;
-use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once};
+use std::time::Instant;
+use tracing::debug;
+use whisper_rs::{
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperSegment,
+    WhisperState, WhisperTokenId,
+};
 
 static INIT_LOGGING: Once = Once::new();
 
@@ -14,10 +25,122 @@ unsafe extern "C" fn silent_log_callback(_level: u32, _msg: *const c_char, _user
     // Do nothing - suppress all logging
 }
 
-/// Transcribes audio samples using Whisper.
-///
-/// Audio should be f32 samples at 16kHz mono.
-pub fn transcribe(audio: &[f32], model_path: &Path, quiet: bool) -> Result<String> {
+/// Per-call transcription settings.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// When set to true, decoding stops early and the segments decoded so
+    /// far are returned instead of an error.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Spoken language code (e.g. "en"), or "auto" to detect it; `None`
+    /// lets the model decide
+    pub language: Option<String>,
+    /// Record per-word timings in each segment
+    pub word_timestamps: bool,
+    /// Decode at a higher temperature with a looser no-speech threshold,
+    /// which can recover quiet speech Whisper otherwise drops as silence
+    pub relaxed: bool,
+    /// Language used under "auto" when the clip is too short to detect one
+    /// (default "en")
+    pub fallback_language: Option<String>,
+    /// Report the detected language and its probability on stderr
+    pub verbose: bool,
+}
+
+/// Sampling temperature used by `Options::relaxed` (whisper.cpp starts at 0).
+const RELAXED_TEMPERATURE: f32 = 0.4;
+
+/// No-speech probability threshold used by `Options::relaxed` (default 0.6).
+const RELAXED_NO_SPEECH_THRESHOLD: f32 = 0.9;
+
+/// How much of the start of a clip language detection looks at, in seconds.
+const DETECT_SECS: u32 = 10;
+
+/// Clips shorter than this (in seconds) get the fallback language instead of
+/// detection, which is unreliable on so little audio.
+const MIN_DETECT_SECS: f32 = 2.0;
+
+/// Fallback when `Options::fallback_language` isn't set.
+const DEFAULT_FALLBACK_LANGUAGE: &str = "en";
+
+/// The result of transcribing a clip.
+#[derive(Debug, Default)]
+pub struct Transcription {
+    /// All segment text joined into one string
+    pub text: String,
+    pub segments: Vec<Segment>,
+    /// Segments whose text wasn't valid UTF-8 and had bytes replaced with U+FFFD
+    pub lossy_segments: usize,
+    /// Decoding error that cut the transcript short; the text holds only the
+    /// segments decoded before it
+    pub error: Option<String>,
+    /// Code of the language Whisper decoded as, detected or not
+    pub language: Option<String>,
+}
+
+/// A span of transcribed audio, with times relative to the clip start.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub text: String,
+    /// Per-word timings, present when word timestamps were requested
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<Word>,
+}
+
+/// A single word with its timing and Whisper's confidence in it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Word {
+    pub start_ms: i64,
+    pub end_ms: i64,
+    /// Word text, including any leading space
+    pub text: String,
+    /// Mean token probability, 0.0-1.0
+    pub probability: f32,
+}
+
+/// Converts a parsed `--lang` value into `Options::language`, which is
+/// passed to Whisper as is: "none" becomes no language, and everything else
+/// (including "en" with an English-only model) is left alone.
+pub fn language_option(code: &str) -> Option<String> {
+    (code != NO_LANGUAGE).then(|| code.to_string())
+}
+
+/// Returns Whisper's supported languages as `(code, name)` pairs.
+pub fn languages() -> Vec<(&'static str, &'static str)> {
+    (0..=whisper_rs::get_lang_max_id())
+        .filter_map(|id| {
+            Some((
+                whisper_rs::get_lang_str(id)?,
+                whisper_rs::get_lang_str_full(id)?,
+            ))
+        })
+        .collect()
+}
+
+/// `--lang` value that sets no language at all, leaving it to the model.
+pub const NO_LANGUAGE: &str = "none";
+
+/// Parses a `--lang` value: a Whisper language code, "auto", or "none".
+pub fn parse_language(value: &str) -> Result<String, String> {
+    let code = value.trim().to_lowercase();
+    if code == "auto" || code == NO_LANGUAGE || whisper_rs::get_lang_id(&code).is_some() {
+        Ok(code)
+    } else {
+        Err(format!(
+            "unknown language '{}' (run `tx languages` for the list)",
+            value
+        ))
+    }
+}
+
+/// Describes the CPU and GPU backends whisper.cpp was built with.
+pub fn system_info() -> &'static str {
+    whisper_rs::print_system_info()
+}
+
+/// Loads a Whisper model into a reusable context.
+pub fn load_model(model_path: &Path, quiet: bool) -> Result<WhisperContext> {
     // Suppress whisper.cpp logging
     INIT_LOGGING.call_once(|| {
         unsafe {
@@ -30,36 +153,328 @@ pub fn transcribe(audio: &[f32], model_path: &Path, quiet: bool) -> Result<Strin
         eprintln!("\x1b[90m(Loading model...)\x1b[0m");
     }
 
+    debug!(path = %model_path.display(), "loading model");
+    let start = Instant::now();
     let ctx = WhisperContext::new_with_params(
         model_path.to_str().context("Invalid model path")?,
         WhisperContextParameters::default(),
     )
-    .context("Failed to load Whisper model")?;
+    .context("Failed to load Whisper model")
+    .code(Code::Model)?;
+    debug!(
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "loaded model"
+    );
+
+    Ok(ctx)
+}
 
+/// Loads the model at `model_path` and transcribes `audio` with it, for
+/// single-shot use; anything transcribing more than once should call
+/// `load_model` once and then `transcribe`.
+pub fn transcribe_file(
+    model_path: &Path,
+    audio: &[f32],
+    opts: &Options,
+    quiet: bool,
+) -> Result<Transcription> {
+    let ctx = load_model(model_path, quiet)?;
+    transcribe(&ctx, audio, opts)
+}
+
+/// Transcribes audio samples with an already-loaded model (see
+/// `load_model`), so repeated calls pay only for decoding.
+///
+/// Audio should be f32 samples at 16kHz mono.
+pub fn transcribe(ctx: &WhisperContext, audio: &[f32], opts: &Options) -> Result<Transcription> {
     let mut state = ctx.create_state().context("Failed to create Whisper state")?;
 
+    // English-only models can't detect anything, so skip straight to decoding
+    let language = match opts.language.as_deref() {
+        Some("auto") if ctx.is_multilingual() => Some(detect_language(&mut state, audio, opts)?),
+        language => language.map(str::to_string),
+    };
+
     let mut params = FullParams::new(SamplingStrategy::BeamSearch { beam_size: 5, patience: -1.0 });
-    params.set_language(Some("en"));
+    params.set_language(language.as_deref());
     params.set_print_special(false);
     params.set_print_progress(false);
     params.set_print_realtime(false);
     params.set_print_timestamps(false);
+    params.set_token_timestamps(opts.word_timestamps);
+    if opts.relaxed {
+        params.set_temperature(RELAXED_TEMPERATURE);
+        params.set_no_speech_thold(RELAXED_NO_SPEECH_THRESHOLD);
+    }
 
-    state
-        .full(params, audio)
-        .context("Failed to transcribe audio")?;
+    if let Some(cancel) = &opts.cancel {
+        let cancel = Arc::clone(cancel);
+        params.set_abort_callback_safe(move || cancel.load(Ordering::Relaxed));
+    }
+
+    debug!(samples = audio.len(), language = ?language, relaxed = opts.relaxed, "decoding");
+    let start = Instant::now();
+    let result = state.full(params, audio);
+    debug!(
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        segments = state.full_n_segments(),
+        ok = result.is_ok(),
+        "decoded"
+    );
+    let cancelled = opts
+        .cancel
+        .as_ref()
+        .is_some_and(|c| c.load(Ordering::Relaxed));
 
-    let num_segments = state.full_n_segments();
+    let error = salvage(result, cancelled, state.full_n_segments())
+        .context("Failed to transcribe audio")?;
 
+    let eot = ctx.token_eot();
     let mut text = String::new();
-    for i in 0..num_segments {
-        if let Some(segment) = state.get_segment(i) {
-            if let Ok(segment_text) = segment.to_str_lossy() {
-                text.push_str(&segment_text);
-                text.push(' ');
+    let mut segments = Vec::new();
+    let mut lossy_segments = 0;
+    for segment in state.as_iter() {
+        // Strict decoding first, so any replacement can be reported
+        let decoded = match segment.to_str() {
+            Ok(s) => Ok(Cow::Borrowed(s)),
+            Err(_) => segment.to_str_lossy(),
+        };
+        if let Ok(segment_text) = decoded {
+            if matches!(segment_text, Cow::Owned(_)) {
+                lossy_segments += 1;
             }
+            text.push_str(&segment_text);
+            text.push(' ');
+            segments.push(Segment {
+                start_ms: segment.start_timestamp() * 10,
+                end_ms: segment.end_timestamp() * 10,
+                text: segment_text.trim().to_string(),
+                words: if opts.word_timestamps {
+                    words(&segment, eot)
+                } else {
+                    Vec::new()
+                },
+            });
         }
     }
 
-    Ok(text.trim().to_string())
+    Ok(Transcription {
+        text: text.trim().to_string(),
+        segments,
+        lossy_segments,
+        error,
+        language: whisper_rs::get_lang_str(state.full_lang_id_from_state()).map(str::to_string),
+    })
+}
+
+/// Decides what a decoding `result` means for the transcript, given whether
+/// it was cancelled and how many segments were decoded.
+///
+/// A failure after some segments were decoded salvages those segments,
+/// returning the error message to report alongside them; with nothing
+/// decoded it's fatal. Cancellation isn't a failure.
+fn salvage<T, E: std::fmt::Display>(
+    result: Result<T, E>,
+    cancelled: bool,
+    decoded: i32,
+) -> Result<Option<String>, E> {
+    match result {
+        Err(_) if cancelled => Ok(None),
+        Err(e) if decoded <= 0 => Err(e),
+        Err(e) => Ok(Some(e.to_string())),
+        Ok(_) => Ok(None),
+    }
+}
+
+/// Transcribes each of `pieces` (sample ranges of 16kHz `audio`) on its own,
+/// so under "auto" each gets its own detected language, and joins the
+/// results. Segment and word times stay relative to the start of `audio`.
+/// The reported language is the one covering the most audio. Experimental:
+/// detection on short pieces is unreliable.
+pub fn transcribe_pieces(
+    ctx: &WhisperContext,
+    audio: &[f32],
+    pieces: &[Range<usize>],
+    opts: &Options,
+) -> Result<Transcription> {
+    let mut joined = Transcription::default();
+    let mut coverage: HashMap<String, usize> = HashMap::new();
+    for piece in pieces {
+        let cancelled = opts
+            .cancel
+            .as_ref()
+            .is_some_and(|c| c.load(Ordering::Relaxed));
+        if cancelled {
+            break;
+        }
+
+        let part = transcribe(ctx, &audio[piece.clone()], opts)?;
+        let offset_ms = piece.start as i64 * 1000 / crate::audio::SAMPLE_RATE as i64;
+        if let Some(language) = &part.language {
+            *coverage.entry(language.clone()).or_default() += piece.len();
+        }
+        if !part.text.is_empty() {
+            if !joined.text.is_empty() {
+                joined.text.push(' ');
+            }
+            joined.text.push_str(&part.text);
+        }
+        joined
+            .segments
+            .extend(part.segments.into_iter().map(|mut segment| {
+                segment.start_ms += offset_ms;
+                segment.end_ms += offset_ms;
+                for word in &mut segment.words {
+                    word.start_ms += offset_ms;
+                    word.end_ms += offset_ms;
+                }
+                segment
+            }));
+        joined.lossy_segments += part.lossy_segments;
+        // Later pieces would leave a gap after the one that failed
+        if part.error.is_some() {
+            joined.error = part.error;
+            break;
+        }
+    }
+    joined.language = coverage
+        .into_iter()
+        .max_by_key(|(_, samples)| *samples)
+        .map(|(language, _)| language);
+    Ok(joined)
+}
+
+/// Detects the spoken language from the first `DETECT_SECS` of `audio`,
+/// falling back to `opts.fallback_language` (with a warning) for clips too
+/// short to judge.
+fn detect_language(state: &mut WhisperState, audio: &[f32], opts: &Options) -> Result<String> {
+    let rate = crate::audio::SAMPLE_RATE;
+    if (audio.len() as f32) < MIN_DETECT_SECS * rate as f32 {
+        let fallback = opts
+            .fallback_language
+            .as_deref()
+            .unwrap_or(DEFAULT_FALLBACK_LANGUAGE);
+        eprintln!(
+            "\x1b[93mWarning:\x1b[0m Clip too short to detect its language; transcribing as '{}'",
+            fallback
+        );
+        return Ok(fallback.to_string());
+    }
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get().min(4));
+    let sample = &audio[..audio.len().min((DETECT_SECS * rate) as usize)];
+    state
+        .pcm_to_mel(sample, threads)
+        .context("Failed to prepare audio for language detection")?;
+    let (id, probabilities) = state
+        .lang_detect(0, threads)
+        .context("Failed to detect language")?;
+    let code = whisper_rs::get_lang_str(id).context("Whisper detected an unknown language")?;
+    debug!(
+        language = code,
+        probability = probabilities.get(id as usize).copied(),
+        "detected language"
+    );
+
+    if opts.verbose {
+        eprintln!(
+            "\x1b[90m(Detected language: {} ({}), p={:.2})\x1b[0m",
+            code,
+            whisper_rs::get_lang_str_full(id).unwrap_or("?"),
+            probabilities.get(id as usize).copied().unwrap_or_default()
+        );
+    }
+
+    Ok(code.to_string())
+}
+
+/// Groups a segment's tokens into words.
+///
+/// A token starting with a space begins a new word. Token bytes are joined
+/// before decoding so characters split across tokens survive intact.
+fn words(segment: &WhisperSegment, eot: WhisperTokenId) -> Vec<Word> {
+    struct Pending {
+        bytes: Vec<u8>,
+        start: i64,
+        end: i64,
+        probability_sum: f32,
+        tokens: usize,
+    }
+
+    fn finish(p: Pending) -> Word {
+        Word {
+            start_ms: p.start * 10,
+            end_ms: p.end * 10,
+            text: String::from_utf8_lossy(&p.bytes).into_owned(),
+            probability: p.probability_sum / p.tokens as f32,
+        }
+    }
+
+    let mut words = Vec::new();
+    let mut pending: Option<Pending> = None;
+
+    for i in 0..segment.n_tokens() {
+        let Some(token) = segment.get_token(i) else {
+            continue;
+        };
+        // Timestamp and control tokens sort after end-of-text
+        if token.token_id() >= eot {
+            continue;
+        }
+        let Ok(bytes) = token.to_bytes() else {
+            continue;
+        };
+        let data = token.token_data();
+
+        match &mut pending {
+            Some(p) if !bytes.starts_with(b" ") => {
+                p.bytes.extend_from_slice(bytes);
+                p.end = data.t1;
+                p.probability_sum += data.p;
+                p.tokens += 1;
+            }
+            _ => {
+                if let Some(p) = pending.take() {
+                    words.push(finish(p));
+                }
+                pending = Some(Pending {
+                    bytes: bytes.to_vec(),
+                    start: data.t0,
+                    end: data.t1,
+                    probability_sum: data.p,
+                    tokens: 1,
+                });
+            }
+        }
+    }
+
+    if let Some(p) = pending {
+        words.push(finish(p));
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn salvage_keeps_segments_decoded_before_a_failure() {
+        let failed: Result<(), &str> = Err("out of memory");
+        assert_eq!(
+            salvage(failed, false, 3),
+            Ok(Some("out of memory".to_string()))
+        );
+        assert_eq!(salvage(failed, false, 0), Err("out of memory"));
+    }
+
+    #[test]
+    fn salvage_ignores_cancellation_and_success() {
+        let failed: Result<(), &str> = Err("aborted");
+        assert_eq!(salvage(failed, true, 0), Ok(None));
+        assert_eq!(salvage(failed, true, 2), Ok(None));
+        assert_eq!(salvage(Ok::<_, &str>(0), false, 0), Ok(None));
+        assert_eq!(salvage(Ok::<_, &str>(0), false, 5), Ok(None));
+    }
 }